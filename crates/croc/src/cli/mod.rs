@@ -1,12 +1,14 @@
 use std::os::unix::fs::PermissionsExt;
 use std::path::PathBuf;
+use std::time::Duration;
 
 use clap::{Args, Parser, Subcommand};
 use tracing::error;
 
-use super::{model, utils};
+use super::{comm, model, utils};
 
 mod receive;
+mod relay;
 mod send;
 
 /// crors(croc - easily and securely transfer stuff from one computer to another) rewrite by rust
@@ -80,6 +82,20 @@ pub struct GlobalArgs {
 
     #[arg(
         long,
+        help = "write the received file to stdout",
+        default_value_t = false
+    )]
+    pub stdout: bool,
+
+    #[arg(
+        long = "save-text",
+        help = "save received text to a file instead of printing it"
+    )]
+    pub save_text: Option<PathBuf>,
+
+    #[arg(
+        long,
+        global = true,
         help = "add a socks5 proxy",
         env = "SOCKS5_PROXY",
         default_value = ""
@@ -88,6 +104,7 @@ pub struct GlobalArgs {
 
     #[arg(
         long,
+        global = true,
         help = "add a http proxy",
         env = "HTTP_PROXY",
         default_value = ""
@@ -105,7 +122,116 @@ pub enum CrocCommand {
     Send(SendArgs),
 
     #[command(name = "relay", about = "start your own relay (optional)")]
-    Relay,
+    Relay(RelayArgs),
+}
+
+#[derive(Args, Debug)]
+pub struct RelayArgs {
+    #[arg(long, help = "host of the relay", default_value = "")]
+    host: String,
+
+    #[arg(
+        long,
+        help = "ports of the relay, the first one is the base port and the rest are for transfers",
+        value_delimiter = ',',
+        default_value = "9009,9010,9011,9012,9013"
+    )]
+    ports: Vec<String>,
+
+    #[arg(
+        long = "room-ttl",
+        help = "close rooms that are not transferring this long after they were opened",
+        value_parser = humantime::parse_duration,
+        default_value = "3h"
+    )]
+    room_ttl: Duration,
+
+    #[arg(
+        long = "room-cleanup-interval",
+        help = "how often to look for rooms past their ttl",
+        value_parser = humantime::parse_duration,
+        default_value = "10m"
+    )]
+    room_cleanup_interval: Duration,
+
+    #[arg(
+        long = "idle-timeout",
+        help = "close transferring rooms after this long without data, e.g. 10m",
+        value_parser = humantime::parse_duration
+    )]
+    idle_timeout: Option<Duration>,
+
+    #[arg(
+        long,
+        help = "serve prometheus metrics at /metrics on this address, e.g. 127.0.0.1:9100",
+        default_value = ""
+    )]
+    metrics: String,
+
+    #[arg(
+        long = "rate-limit",
+        help = "new connections per second allowed from one ip, 0 for no limit",
+        default_value_t = 0.0
+    )]
+    rate_limit: f64,
+
+    #[arg(
+        long = "rate-burst",
+        help = "connections one ip can open at once before the rate limit applies",
+        default_value_t = 10
+    )]
+    rate_burst: u32,
+
+    #[arg(
+        long = "max-rooms-per-ip",
+        help = "rooms one ip can be in at the same time, 0 for no limit",
+        default_value_t = 0
+    )]
+    max_rooms_per_ip: usize,
+
+    #[arg(
+        long = "max-connections",
+        help = "connections the relay holds at the same time, 0 for no limit",
+        default_value_t = 0
+    )]
+    max_connections: usize,
+
+    #[arg(
+        long = "access-list",
+        help = "file of \"allow <cidr>\" and \"deny <cidr>\" lines, reloaded on SIGHUP",
+        default_value = ""
+    )]
+    access_list: String,
+
+    #[arg(
+        long = "password-backoff",
+        help = "wait after a bad password from an ip, doubled for each further one, 0 to turn off",
+        value_parser = humantime::parse_duration,
+        default_value = "1s"
+    )]
+    password_backoff: Duration,
+
+    #[arg(
+        long = "password-ban-after",
+        help = "bad passwords in a row before an ip is banned, 0 to never ban",
+        default_value_t = 10
+    )]
+    password_ban_after: u32,
+
+    #[arg(
+        long = "password-ban",
+        help = "how long an ip is banned for",
+        value_parser = humantime::parse_duration,
+        default_value = "15m"
+    )]
+    password_ban: Duration,
+
+    #[arg(
+        long,
+        help = "file of tenants with their own passwords and limits, used instead of the relay password",
+        default_value = ""
+    )]
+    credentials: String,
 }
 
 #[derive(Args, Debug)]
@@ -132,8 +258,12 @@ pub struct SendArgs {
     #[arg(long = "no-local", help = "disable local relay when sending")]
     no_local: Option<bool>,
 
-    #[arg(long = "no-multi", help = "disable multiplexing")]
-    no_multi: Option<bool>,
+    #[arg(
+        long = "no-multi",
+        help = "disable multiplexing",
+        action = clap::ArgAction::SetTrue
+    )]
+    no_multi: bool,
 
     #[arg(
         long = "git",
@@ -156,6 +286,13 @@ pub struct SendArgs {
     )]
     transfers: usize,
 
+    #[arg(
+        long,
+        help = "limit the upload speed over all connections, e.g. 500k or 5M per second",
+        default_value = ""
+    )]
+    throttle: String,
+
     #[arg(
         long,
         short = 'c',
@@ -174,8 +311,8 @@ impl App {
                     send::send(args, &self.global)?;
                     return Ok(());
                 },
-                CrocCommand::Relay => {
-                    dbg!("Relay");
+                CrocCommand::Relay(args) => {
+                    return relay::relay(args, &self.global);
                 },
             }
         }
@@ -264,6 +401,18 @@ fn determine_pass(pass: &str) -> String {
     rst
 }
 
+// the proxies are global, every connection to a relay goes through them
+fn set_proxies(global: &GlobalArgs) {
+    {
+        let mut lock = comm::SOCKS5_PROXY.write();
+        *lock = global.socks5.clone();
+    }
+    {
+        let mut lock = comm::HTTP_PROXY.write();
+        *lock = global.connect.clone();
+    }
+}
+
 fn get_classic_config_file(require: bool) -> PathBuf {
     match utils::get_config_dir(require) {
         Err(e) => {
@@ -1,15 +1,8 @@
-use super::{determine_pass, GlobalArgs};
-use crate::{comm, croc};
+use super::{determine_pass, set_proxies, GlobalArgs};
+use crate::{croc, utils};
 
 pub(super) fn receive(global: &GlobalArgs) -> anyhow::Result<()> {
-    {
-        let mut lock = comm::SOCKS5_PROXY.write();
-        *lock = global.socks5.clone();
-    }
-    {
-        let mut lock = comm::HTTP_PROXY.write();
-        *lock = global.connect.clone();
-    }
+    set_proxies(global);
     let mut opts = croc::Options {
         shared_secret: "".into(),
         is_sender: false,
@@ -20,22 +13,52 @@ pub(super) fn receive(global: &GlobalArgs) -> anyhow::Result<()> {
         relay_password: determine_pass(&global.pass),
         disable_local: false,
         only_local: global.local,
+        no_multi: false,
+        hash_algorithm: Default::default(),
+        text: None,
+        stdout: global.stdout,
         relay_ports: vec![],
         ip: global.ip.clone(),
+        throttle: None,
     };
     let len = global.args.len();
     match len {
         1 => {
             opts.shared_secret = global.args.first().cloned().unwrap();
         },
-        3 | 4 => {
+        // the pin and the words of a code given as separate arguments
+        3..=5 => {
             opts.shared_secret = global.args.clone().join("-");
         },
         _ => {},
     }
+    if opts.shared_secret.is_empty() {
+        opts.shared_secret = utils::get_input(b"Enter receive code: ")?;
+    }
+    if opts.shared_secret.is_empty() {
+        anyhow::bail!("need a code to receive")
+    }
     // load options here
     // setDebugLevel(c)
     // let do_remember = global.remember;
     let mut cr = croc::new(opts)?;
-    cr.receive()
+    let rst = cr.receive()?;
+    if let Some(text) = rst.text {
+        match &global.save_text {
+            Some(fname) => {
+                std::fs::write(fname, text)?;
+                eprintln!("Saved text to {}", fname.display());
+            },
+            None => println!("{}", text),
+        }
+        return Ok(());
+    }
+    let size: u64 = rst.files.iter().map(|x| x.size).sum();
+    eprintln!(
+        "Received {} files ({}) and {} empty folders",
+        rst.files.len(),
+        utils::byte_count_decimal(size),
+        rst.empty_folders.len()
+    );
+    Ok(())
 }
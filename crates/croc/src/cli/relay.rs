@@ -0,0 +1,105 @@
+use std::path::Path;
+use std::sync::Arc;
+
+use tokio::signal::unix::{signal, SignalKind};
+use tokio::task::JoinSet;
+use tracing::{error, info};
+
+use super::{determine_pass, GlobalArgs, RelayArgs};
+use crate::tcp;
+
+pub(super) fn relay(
+    args: &RelayArgs,
+    global: &GlobalArgs,
+) -> anyhow::Result<()> {
+    if args.ports.is_empty() {
+        anyhow::bail!("need at least one port for the relay")
+    }
+    let host = if args.host.is_empty() { "0.0.0.0" } else { args.host.as_str() };
+    let password = determine_pass(&global.pass);
+    // the base port tells clients which ports to use for the transfers
+    let tcp_ports = args.ports[1..].join(",");
+    if args.room_cleanup_interval.is_zero() {
+        anyhow::bail!("the room cleanup interval can not be zero")
+    }
+    if args.rate_limit < 0.0 || !args.rate_limit.is_finite() {
+        anyhow::bail!("the rate limit must be zero or a positive number")
+    }
+    let limits = tcp::Limits {
+        connection_rate: args.rate_limit,
+        connection_burst: args.rate_burst,
+        max_rooms_per_ip: args.max_rooms_per_ip,
+        max_connections: args.max_connections,
+    };
+    let access = match args.access_list.as_str() {
+        "" => tcp::Access::default(),
+        path => tcp::Access::load(Path::new(path))?,
+    };
+    let tenants = match args.credentials.as_str() {
+        "" => tcp::Tenants::default(),
+        path => tcp::Tenants::load(path)?,
+    };
+    let options = tcp::Options {
+        room_ttl: args.room_ttl,
+        room_cleanup_interval: args.room_cleanup_interval,
+        idle_timeout: args.idle_timeout.filter(|x| !x.is_zero()),
+        metrics: Default::default(),
+        limiter: Arc::new(tcp::Limiter::new(limits)),
+        access: Arc::new(access),
+        lockout: Arc::new(tcp::Lockout::new(tcp::LockoutPolicy {
+            backoff: args.password_backoff,
+            ban_after: args.password_ban_after,
+            ban: args.password_ban,
+        })),
+        tenants: Arc::new(tenants),
+    };
+
+    // one runtime serves every port, each waiting client is a task rather than a thread
+    let rt = tokio::runtime::Builder::new_multi_thread().enable_all().build()?;
+    rt.block_on(async {
+        let mut servers = JoinSet::new();
+        for (i, port) in args.ports.iter().enumerate() {
+            let host = host.to_string();
+            let port = port.clone();
+            let password = password.clone();
+            let banner = if i == 0 { tcp_ports.clone() } else { String::new() };
+            let options = options.clone();
+            servers.spawn(async move { tcp::run(&host, port, password, banner, options).await });
+        }
+
+        if !args.metrics.is_empty() {
+            servers.spawn(tcp::metrics::serve(args.metrics.clone(), options.metrics.clone()));
+        }
+
+        // keep running until we are told to stop, or one of the servers gives up
+        let mut terminate = signal(SignalKind::terminate())?;
+        let mut hangup = signal(SignalKind::hangup())?;
+        loop {
+            tokio::select! {
+                _ = tokio::signal::ctrl_c() => {
+                    info!("got interrupt, shutting down relay");
+                    return Ok(());
+                },
+                _ = terminate.recv() => {
+                    info!("got terminate, shutting down relay");
+                    return Ok(());
+                },
+                _ = hangup.recv() => {
+                    // a broken file must not take the relay down, the old list stays
+                    if let Err(e) = options.access.reload() {
+                        error!("keeping the old access list: {:#}", e);
+                    }
+                },
+                Some(rst) = servers.join_next() => {
+                    let e = match rst {
+                        Ok(Err(e)) => e,
+                        Ok(Ok(())) => anyhow::anyhow!("relay server stopped"),
+                        Err(e) => e.into(),
+                    };
+                    error!(error = ?e);
+                    return Err(e);
+                },
+            }
+        }
+    })
+}
@@ -1,22 +1,30 @@
-use super::{determine_pass, GlobalArgs, SendArgs};
-use crate::croc;
+use super::{determine_pass, set_proxies, GlobalArgs, SendArgs};
+use crate::{croc, model, utils};
 
 pub(super) fn send(
     args: &SendArgs,
     global: &GlobalArgs,
 ) -> anyhow::Result<()> {
+    set_proxies(global);
     let port_param: u16 = if args.port == 0 { 9009 } else { args.port };
     let transfers_param: usize = if args.transfers == 0 { 4 } else { args.transfers };
     let mut ports = Vec::with_capacity(transfers_param + 1);
     for i in 0..(transfers_param + 1) {
         ports.push((port_param + i as u16).to_string());
     }
+    let shared_secret = if args.code.is_empty() {
+        utils::get_random_name()
+    } else if args.code.len() < model::MIN_CODE_LENGTH {
+        anyhow::bail!("code is too short, use at least {} characters", model::MIN_CODE_LENGTH)
+    } else {
+        args.code.clone()
+    };
+    let throttle = match args.throttle.as_str() {
+        "" => None,
+        x => Some(utils::parse_byte_count(x)?).filter(|x| *x > 0),
+    };
     let opts = croc::Options {
-        // @fri3nd TODO
-        // todo!()
-        shared_secret: "8971-djkasjd".into(),
-        // @fri3nd TODO
-        // todo!()
+        shared_secret,
         is_sender: true,
         zip_folder: args.zip,
         git_ignore: args.git,
@@ -25,15 +33,23 @@ pub(super) fn send(
         relay_password: determine_pass(&global.pass),
         disable_local: args.no_local.unwrap_or(false),
         only_local: global.local,
+        no_multi: args.no_multi,
+        hash_algorithm: args.hash.parse()?,
+        text: args.text.clone(),
+        stdout: false,
         relay_ports: ports,
         ip: "".into(),
+        throttle,
     };
     // xxxxxxxxxxxx
     // xxxxxxxxxxxx
     // xxxxxxxxxxxx
     // xxxxxxxxxxxx
-    let (minimal_file_infos, empty_folders_to_transfer, total_number_folders) =
-        croc::get_files_info(&args.fnames, opts.zip_folder, opts.git_ignore)?;
+    let (minimal_file_infos, empty_folders_to_transfer, total_number_folders) = match opts.text {
+        Some(_) if !args.fnames.is_empty() => anyhow::bail!("can't send text and files together"),
+        Some(_) => (vec![], vec![], 0),
+        None => croc::get_files_info(&args.fnames, opts.zip_folder, opts.git_ignore)?,
+    };
     let mut cr = croc::new(opts)?;
 
     // save the config
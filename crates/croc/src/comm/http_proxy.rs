@@ -0,0 +1,161 @@
+use std::io::{Read, Write};
+use std::net::TcpStream;
+use std::time::Duration;
+
+use anyhow::Context;
+use base64::prelude::{Engine, BASE64_STANDARD};
+use tracing::debug;
+
+use super::proxy_url;
+
+// the reply of a proxy is only headers, anything longer is not a proxy talking
+const MAX_REPLY_SIZE: usize = 16 * 1024;
+
+// Dial connects to address through a http proxy with CONNECT,
+// proxy is host:port or http://[user:pass@]host:port
+pub fn dial(
+    proxy: &str,
+    address: &str,
+    timelimit: Duration,
+) -> anyhow::Result<TcpStream> {
+    let proxy = proxy_url::parse(proxy, &["http"], 80)?;
+    let mut stream = proxy_url::connect(&proxy.address, timelimit)?;
+
+    // the handshake gets the same time as the connection, the tunnel is unlimited
+    stream.set_read_timeout(Some(timelimit))?;
+    stream.set_write_timeout(Some(timelimit))?;
+    handshake(&mut stream, proxy.auth(), address).with_context(|| {
+        format!("http proxy {} could not connect to {}", proxy.address, address)
+    })?;
+    stream.set_read_timeout(None)?;
+    stream.set_write_timeout(None)?;
+    Ok(stream)
+}
+
+// Handshake asks the proxy on stream to open a tunnel to address,
+// with basic authentication when there are credentials
+pub fn handshake<S: Read + Write>(
+    stream: &mut S,
+    auth: Option<(&str, &str)>,
+    address: &str,
+) -> anyhow::Result<()> {
+    let mut request = format!("CONNECT {0} HTTP/1.1\r\nHost: {0}\r\n", address);
+    if let Some((user, pass)) = auth {
+        let credentials = BASE64_STANDARD.encode(format!("{}:{}", user, pass));
+        request.push_str(&format!("Proxy-Authorization: Basic {}\r\n", credentials));
+    }
+    request.push_str("\r\n");
+    stream.write_all(request.as_bytes())?;
+
+    let reply = read_reply(stream)?;
+    let status_line = reply.lines().next().unwrap_or_default();
+    let mut parts = status_line.splitn(3, ' ');
+    let (version, code) = (parts.next().unwrap_or_default(), parts.next().unwrap_or_default());
+    if !version.starts_with("HTTP/") {
+        anyhow::bail!("proxy did not answer with http: {:?}", status_line)
+    }
+    match code {
+        "200" => {
+            debug!("http proxy connected to {}", address);
+            Ok(())
+        },
+        "407" if auth.is_some() => {
+            anyhow::bail!("proxy rejected the credentials ({})", status_line)
+        },
+        "407" => anyhow::bail!("proxy requires authentication ({})", status_line),
+        _ => anyhow::bail!("proxy refused to connect: {}", status_line),
+    }
+}
+
+// read the reply up to the empty line, one byte at a time
+// so nothing of the tunnel behind it is consumed
+fn read_reply<S: Read>(stream: &mut S) -> anyhow::Result<String> {
+    let mut reply = Vec::with_capacity(256);
+    let mut byte = [0u8; 1];
+    while !reply.ends_with(b"\r\n\r\n") {
+        if reply.len() >= MAX_REPLY_SIZE {
+            anyhow::bail!("proxy reply is longer than {} bytes", MAX_REPLY_SIZE)
+        }
+        if stream.read(&mut byte)? == 0 {
+            anyhow::bail!("proxy closed the connection before replying")
+        }
+        reply.push(byte[0]);
+    }
+    Ok(String::from_utf8_lossy(&reply).into_owned())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::TcpListener;
+    use std::thread::{self, JoinHandle};
+
+    use super::*;
+
+    // proxy reads the request on a connection on localhost and answers it with reply,
+    // the other end is returned and the request comes back from the thread
+    fn proxy(reply: Vec<u8>) -> (TcpStream, JoinHandle<String>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let handle = thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            stream.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+            let request = read_reply(&mut stream).unwrap();
+            // the client may hang up before all of it is written
+            let _ = stream.write_all(&reply);
+            request
+        });
+        let stream = TcpStream::connect(addr).unwrap();
+        stream.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+        (stream, handle)
+    }
+
+    #[test]
+    fn connected() {
+        let reply = b"HTTP/1.1 200 Connection established\r\n\r\ntunnel".to_vec();
+        let (mut stream, handle) = proxy(reply);
+        handshake(&mut stream, None, "relay.example.com:9009").unwrap();
+        assert_eq!(
+            handle.join().unwrap(),
+            "CONNECT relay.example.com:9009 HTTP/1.1\r\nHost: relay.example.com:9009\r\n\r\n"
+        );
+        // what follows the reply belongs to the tunnel
+        let mut rest = String::new();
+        stream.read_to_string(&mut rest).unwrap();
+        assert_eq!(rest, "tunnel");
+    }
+
+    #[test]
+    fn credentials_rejected() {
+        let reply = b"HTTP/1.1 407 Proxy Authentication Required\r\n\r\n".to_vec();
+        let (mut stream, handle) = proxy(reply);
+        let err = handshake(&mut stream, Some(("alice", "secret")), "127.0.0.1:9009").unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "proxy rejected the credentials (HTTP/1.1 407 Proxy Authentication Required)"
+        );
+        let request = handle.join().unwrap();
+        assert!(request.contains("Proxy-Authorization: Basic YWxpY2U6c2VjcmV0\r\n"));
+    }
+
+    #[test]
+    fn authentication_required() {
+        let reply = b"HTTP/1.1 407 Proxy Authentication Required\r\n\r\n".to_vec();
+        let (mut stream, handle) = proxy(reply);
+        let err = handshake(&mut stream, None, "127.0.0.1:9009").unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "proxy requires authentication (HTTP/1.1 407 Proxy Authentication Required)"
+        );
+        assert!(!handle.join().unwrap().contains("Proxy-Authorization"));
+    }
+
+    #[test]
+    fn oversized_reply() {
+        let mut reply = b"HTTP/1.1 200 OK\r\n".to_vec();
+        reply.resize(MAX_REPLY_SIZE + 1024, b'x');
+        let (mut stream, handle) = proxy(reply);
+        let err = handshake(&mut stream, None, "127.0.0.1:9009").unwrap_err();
+        assert_eq!(err.to_string(), format!("proxy reply is longer than {} bytes", MAX_REPLY_SIZE));
+        handle.join().unwrap();
+    }
+}
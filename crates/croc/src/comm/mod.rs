@@ -0,0 +1,338 @@
+use std::io::{ErrorKind, Read, Write};
+use std::net::{IpAddr, SocketAddr, TcpStream, ToSocketAddrs};
+use std::time::Duration;
+
+use anyhow::Context;
+use byteorder::{ByteOrder, LittleEndian, WriteBytesExt};
+use parking_lot::RwLock;
+use socket2::Socket;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tracing::{debug, warn};
+
+pub mod http_proxy;
+mod proxy_url;
+pub mod socks5;
+
+const MAGIC_BYTES: &[u8] = b"croc";
+
+// DEFAULT_MAX_FRAME_SIZE is the largest message read unless set otherwise,
+// enough for file chunks and the file list of big folders
+pub const DEFAULT_MAX_FRAME_SIZE: usize = 64 * 1024 * 1024;
+
+lazy_static::lazy_static! {
+    pub static ref SOCKS5_PROXY: RwLock<String> = RwLock::new(String::from(""));
+    pub static ref HTTP_PROXY: RwLock<String> = RwLock::new(String::from(""));
+}
+
+// Comm is some basic TCP communication
+#[derive(Debug)]
+pub struct Comm {
+    socket: Socket,
+    addr: SocketAddr,
+    max_frame_size: usize,
+}
+
+impl Comm {
+    // SetMaxFrameSize limits the size of the messages that will be read
+    pub fn set_max_frame_size(
+        &mut self,
+        max: usize,
+    ) {
+        self.max_frame_size = max;
+    }
+}
+
+pub fn new(
+    stream: Socket,
+    addr: SocketAddr,
+) -> Comm {
+    Comm {
+        socket: stream,
+        addr,
+        max_frame_size: DEFAULT_MAX_FRAME_SIZE,
+    }
+}
+
+// NewConnection gets a new comm to a tcp address,
+// through the socks5 or else the http proxy when one is set and the address is not local
+pub fn new_connection(
+    address: &str,
+    timelimit: Duration,
+) -> anyhow::Result<Comm> {
+    let socks5_proxy = SOCKS5_PROXY.read().clone();
+    if !socks5_proxy.is_empty() && !is_local_address(address) {
+        debug!("connecting to {} through socks5 proxy", address);
+        let stream = socks5::dial(&socks5_proxy, address, timelimit)?;
+        let addr = stream.peer_addr()?;
+        return Ok(new(Socket::from(stream), addr));
+    }
+    let http_proxy = HTTP_PROXY.read().clone();
+    if !http_proxy.is_empty() && !is_local_address(address) {
+        debug!("connecting to {} through http proxy", address);
+        let stream = http_proxy::dial(&http_proxy, address, timelimit)?;
+        let addr = stream.peer_addr()?;
+        return Ok(new(Socket::from(stream), addr));
+    }
+    let addr = address
+        .to_socket_addrs()
+        .with_context(|| format!("could not resolve {}", address))?
+        .next()
+        .ok_or(anyhow::anyhow!("no address found for {}", address))?;
+    let stream = TcpStream::connect_timeout(&addr, timelimit)
+        .with_context(|| format!("could not connect to {}", address))?;
+    Ok(new(Socket::from(stream), addr))
+}
+
+// only this machine is reached directly, everything else may be behind the proxy
+fn is_local_address(address: &str) -> bool {
+    let host = address.rsplit_once(':').map_or(address, |x| x.0);
+    let host = host.trim_start_matches('[').trim_end_matches(']');
+    match host.parse::<IpAddr>() {
+        Ok(ip) => ip.is_loopback(),
+        Err(_) => host == "localhost",
+    }
+}
+
+// Send a message
+impl Comm {
+    pub fn send(
+        &mut self,
+        message: &[u8],
+    ) -> anyhow::Result<()> {
+        let _ = self.write(message)?;
+        Ok(())
+    }
+
+    pub fn write(
+        &mut self,
+        message: &[u8],
+    ) -> anyhow::Result<usize> {
+        let buf = frame(message)?;
+        if let Err(e) = self.socket.write_all(&buf) {
+            anyhow::bail!("connection.Write failed: {:?}", e)
+        }
+
+        Ok(buf.len())
+    }
+
+    pub fn receive(&mut self) -> Result<Vec<u8>, CommError> {
+        let (b, _) = self.read()?;
+        Ok(b)
+    }
+
+    pub fn read(&mut self) -> Result<(Vec<u8>, usize), CommError> {
+        // long read deadline in case waiting for file
+        if let Err(e) = self.socket.set_read_timeout(Some(Duration::from_secs(3 * 3600))) {
+            warn!(target: "setting read deadline", error = ?e);
+        }
+        let mut header = [0u8; 4];
+        read_exact(&mut self.socket, &mut header)?;
+        if header != MAGIC_BYTES {
+            return Err(CommError::BadMagic(header));
+        }
+        // shorten the reading deadline in case getting weird data
+        if let Err(e) = self.socket.set_read_timeout(Some(Duration::from_secs(10))) {
+            warn!(target: "setting read deadline", error = ?e);
+        }
+        read_exact(&mut self.socket, &mut header)?;
+        let num_bytes = LittleEndian::read_u32(&header) as usize;
+        if num_bytes > self.max_frame_size {
+            return Err(CommError::Oversized {
+                size: num_bytes,
+                max: self.max_frame_size,
+            });
+        }
+        let mut buf = vec![0; num_bytes];
+        read_exact(&mut self.socket, &mut buf)?;
+
+        Ok((buf, num_bytes))
+    }
+}
+
+// AsyncComm speaks the same protocol as Comm on a tokio stream,
+// so the relay can wait on many rooms without a thread for each connection
+#[derive(Debug)]
+pub struct AsyncComm {
+    stream: tokio::net::TcpStream,
+    addr: SocketAddr,
+    max_frame_size: usize,
+}
+
+pub fn new_async(
+    stream: tokio::net::TcpStream,
+    addr: SocketAddr,
+) -> AsyncComm {
+    AsyncComm {
+        stream,
+        addr,
+        max_frame_size: DEFAULT_MAX_FRAME_SIZE,
+    }
+}
+
+impl AsyncComm {
+    pub fn addr(&self) -> SocketAddr {
+        self.addr
+    }
+
+    // SetMaxFrameSize limits the size of the messages that will be read
+    pub fn set_max_frame_size(
+        &mut self,
+        max: usize,
+    ) {
+        self.max_frame_size = max;
+    }
+
+    // Close says goodbye properly, so the peer reads everything sent before
+    pub async fn close(mut self) {
+        if let Err(e) = self.stream.shutdown().await {
+            debug!("could not close connection to {}: {:?}", self.addr, e);
+        }
+    }
+
+    // IntoStream gives up the framing, to pipe raw bytes
+    pub fn into_stream(self) -> tokio::net::TcpStream {
+        self.stream
+    }
+
+    pub async fn send(
+        &mut self,
+        message: &[u8],
+    ) -> anyhow::Result<()> {
+        let buf = frame(message)?;
+        if let Err(e) = self.stream.write_all(&buf).await {
+            anyhow::bail!("connection.Write failed: {:?}", e)
+        }
+        Ok(())
+    }
+
+    pub async fn receive(&mut self) -> Result<Vec<u8>, CommError> {
+        let (b, _) = self.read().await?;
+        Ok(b)
+    }
+
+    pub async fn read(&mut self) -> Result<(Vec<u8>, usize), CommError> {
+        // long read deadline in case waiting for file
+        let mut header = [0u8; 4];
+        read_exact_timeout(&mut self.stream, &mut header, Duration::from_secs(3 * 3600)).await?;
+        if header != MAGIC_BYTES {
+            return Err(CommError::BadMagic(header));
+        }
+        // shorten the reading deadline in case getting weird data
+        read_exact_timeout(&mut self.stream, &mut header, Duration::from_secs(10)).await?;
+        let num_bytes = LittleEndian::read_u32(&header) as usize;
+        if num_bytes > self.max_frame_size {
+            return Err(CommError::Oversized {
+                size: num_bytes,
+                max: self.max_frame_size,
+            });
+        }
+        let mut buf = vec![0; num_bytes];
+        read_exact_timeout(&mut self.stream, &mut buf, Duration::from_secs(10)).await?;
+
+        Ok((buf, num_bytes))
+    }
+}
+
+// CommError tells why a message could not be read
+#[derive(Debug)]
+pub enum CommError {
+    // the other side does not speak the croc protocol
+    BadMagic([u8; 4]),
+    // the header announced more than the maximum frame size
+    Oversized { size: usize, max: usize },
+    Timeout,
+    PeerClosed,
+    Io(std::io::Error),
+}
+
+impl std::fmt::Display for CommError {
+    fn fmt(
+        &self,
+        f: &mut std::fmt::Formatter<'_>,
+    ) -> std::fmt::Result {
+        match self {
+            CommError::BadMagic(x) => write!(f, "initial bytes are not magic: {:?}", x),
+            CommError::Oversized { size, max } => {
+                write!(f, "frame of {} bytes is larger than the maximum of {}", size, max)
+            },
+            CommError::Timeout => write!(f, "timed out reading from peer"),
+            CommError::PeerClosed => write!(f, "peer closed the connection"),
+            CommError::Io(e) => write!(f, "read error: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for CommError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            CommError::Io(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<std::io::Error> for CommError {
+    fn from(e: std::io::Error) -> Self {
+        match e.kind() {
+            ErrorKind::UnexpectedEof | ErrorKind::ConnectionReset | ErrorKind::BrokenPipe => {
+                CommError::PeerClosed
+            },
+            ErrorKind::WouldBlock | ErrorKind::TimedOut => CommError::Timeout,
+            _ => CommError::Io(e),
+        }
+    }
+}
+
+// frame puts the magic bytes and the length in front of a message
+fn frame(message: &[u8]) -> anyhow::Result<Vec<u8>> {
+    let mut buf = Vec::with_capacity(MAGIC_BYTES.len() + 4 + message.len());
+    buf.extend_from_slice(MAGIC_BYTES);
+    WriteBytesExt::write_u32::<LittleEndian>(&mut buf, message.len() as u32)?;
+    buf.extend_from_slice(message);
+    Ok(buf)
+}
+
+// fill buf completely, a frame may arrive in several pieces
+fn read_exact(
+    socket: &mut Socket,
+    buf: &mut [u8],
+) -> Result<(), CommError> {
+    socket.read_exact(buf)?;
+    Ok(())
+}
+
+async fn read_exact_timeout(
+    stream: &mut tokio::net::TcpStream,
+    buf: &mut [u8],
+    timeout: Duration,
+) -> Result<(), CommError> {
+    match tokio::time::timeout(timeout, stream.read_exact(buf)).await {
+        Err(_) => Err(CommError::Timeout),
+        Ok(rst) => {
+            rst?;
+            Ok(())
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn only_loopback_skips_the_proxy() {
+        for address in ["127.0.0.1:9009", "[::1]:9009", "localhost:9009"] {
+            assert!(is_local_address(address), "{}", address);
+        }
+        for address in [
+            "10.0.0.5:9009",
+            "172.16.1.1:9009",
+            "192.168.1.20:9009",
+            "169.254.0.7:9009",
+            "[fd00::1]:9009",
+            "croc.example.com:9009",
+        ] {
+            assert!(!is_local_address(address), "{}", address);
+        }
+    }
+}
@@ -0,0 +1,87 @@
+use std::net::{TcpStream, ToSocketAddrs};
+use std::time::Duration;
+
+use anyhow::Context;
+
+// ProxyUrl is a proxy given as [scheme://][user:pass@]host[:port]
+#[derive(Debug)]
+pub struct ProxyUrl {
+    pub address: String,
+    pub user: Option<String>,
+    pub pass: String,
+}
+
+impl ProxyUrl {
+    pub fn auth(&self) -> Option<(&str, &str)> {
+        self.user.as_deref().map(|user| (user, self.pass.as_str()))
+    }
+}
+
+// Parse reads a proxy url with one of the schemes, or none,
+// credentials may be percent encoded
+pub fn parse(
+    s: &str,
+    schemes: &[&str],
+    default_port: u16,
+) -> anyhow::Result<ProxyUrl> {
+    let rest = match s.split_once("://") {
+        Some((scheme, rest)) => {
+            if !schemes.iter().any(|x| x.eq_ignore_ascii_case(scheme)) {
+                anyhow::bail!(
+                    "unsupported proxy scheme {:?}, expected one of {:?}",
+                    scheme,
+                    schemes
+                )
+            }
+            rest
+        },
+        None => s,
+    };
+    let rest = rest.trim_end_matches('/');
+    let (user, pass, host) = match rest.rsplit_once('@') {
+        Some((userinfo, host)) => {
+            let (user, pass) = userinfo.split_once(':').unwrap_or((userinfo, ""));
+            (Some(percent_decode(user)?), percent_decode(pass)?, host)
+        },
+        None => (None, String::new(), rest),
+    };
+    if host.is_empty() {
+        anyhow::bail!("missing host in proxy {:?}", s)
+    }
+    // a port is whatever follows the last colon, unless it is part of an ipv6 address
+    let has_port = host.rsplit_once(':').is_some_and(|(h, _)| !h.contains(':') || h.ends_with(']'));
+    let address = if has_port { host.to_string() } else { format!("{}:{}", host, default_port) };
+    Ok(ProxyUrl { address, user, pass })
+}
+
+// Connect opens the tcp connection to the proxy itself
+pub fn connect(
+    address: &str,
+    timelimit: Duration,
+) -> anyhow::Result<TcpStream> {
+    let addr = address
+        .to_socket_addrs()
+        .with_context(|| format!("could not resolve proxy {}", address))?
+        .next()
+        .ok_or(anyhow::anyhow!("no address found for proxy {}", address))?;
+    TcpStream::connect_timeout(&addr, timelimit)
+        .with_context(|| format!("could not connect to proxy {}", address))
+}
+
+fn percent_decode(s: &str) -> anyhow::Result<String> {
+    let mut out = Vec::with_capacity(s.len());
+    let mut bytes = s.bytes();
+    while let Some(b) = bytes.next() {
+        if b != b'%' {
+            out.push(b);
+            continue;
+        }
+        let hex = [bytes.next().unwrap_or(0), bytes.next().unwrap_or(0)];
+        let decoded = std::str::from_utf8(&hex)
+            .ok()
+            .and_then(|x| u8::from_str_radix(x, 16).ok())
+            .ok_or(anyhow::anyhow!("invalid percent encoding in {:?}", s))?;
+        out.push(decoded);
+    }
+    Ok(String::from_utf8(out)?)
+}
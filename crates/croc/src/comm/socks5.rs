@@ -0,0 +1,266 @@
+use std::io::{Read, Write};
+use std::net::{IpAddr, TcpStream};
+use std::time::Duration;
+
+use anyhow::Context;
+use tracing::debug;
+
+use super::proxy_url;
+
+const VERSION: u8 = 5;
+const NO_AUTH: u8 = 0;
+const USER_PASS_AUTH: u8 = 2;
+const NO_ACCEPTABLE_METHODS: u8 = 0xff;
+const USER_PASS_VERSION: u8 = 1;
+const CMD_CONNECT: u8 = 1;
+const ATYP_IPV4: u8 = 1;
+const ATYP_DOMAIN: u8 = 3;
+const ATYP_IPV6: u8 = 4;
+
+// Dial connects to address through the socks5 proxy,
+// proxy is host:port or socks5://[user:pass@]host:port
+pub fn dial(
+    proxy: &str,
+    address: &str,
+    timelimit: Duration,
+) -> anyhow::Result<TcpStream> {
+    let proxy = proxy_url::parse(proxy, &["socks5", "socks5h"], 1080)?;
+    let (host, port) = split_host_port(address)?;
+    let mut stream = proxy_url::connect(&proxy.address, timelimit)?;
+
+    // the handshake gets the same time as the connection, the tunnel is unlimited
+    stream.set_read_timeout(Some(timelimit))?;
+    stream.set_write_timeout(Some(timelimit))?;
+    handshake(&mut stream, proxy.auth(), host, port).with_context(|| {
+        format!("socks5 proxy {} could not connect to {}", proxy.address, address)
+    })?;
+    stream.set_read_timeout(None)?;
+    stream.set_write_timeout(None)?;
+    Ok(stream)
+}
+
+// Handshake asks the proxy on stream to connect to host:port.
+// Host names are sent as they are, so the proxy resolves them.
+pub fn handshake<S: Read + Write>(
+    stream: &mut S,
+    auth: Option<(&str, &str)>,
+    host: &str,
+    port: u16,
+) -> anyhow::Result<()> {
+    // offer user/pass authentication only when there is something to offer
+    let methods: &[u8] = if auth.is_some() { &[NO_AUTH, USER_PASS_AUTH] } else { &[NO_AUTH] };
+    let mut greeting = vec![VERSION, methods.len() as u8];
+    greeting.extend_from_slice(methods);
+    stream.write_all(&greeting)?;
+
+    let mut reply = [0u8; 2];
+    stream.read_exact(&mut reply)?;
+    if reply[0] != VERSION {
+        anyhow::bail!("proxy answered with version {}, not socks5", reply[0])
+    }
+    match (reply[1], auth) {
+        (NO_AUTH, _) => {},
+        (USER_PASS_AUTH, Some((user, pass))) => authenticate(stream, user, pass)?,
+        (NO_ACCEPTABLE_METHODS, None) => anyhow::bail!("proxy requires authentication"),
+        (NO_ACCEPTABLE_METHODS, Some(_)) => {
+            anyhow::bail!("proxy does not accept username/password authentication")
+        },
+        (method, _) => anyhow::bail!("proxy chose unsupported authentication method {}", method),
+    }
+
+    let mut request = vec![VERSION, CMD_CONNECT, 0];
+    match host.parse::<IpAddr>() {
+        Ok(IpAddr::V4(ip)) => {
+            request.push(ATYP_IPV4);
+            request.extend_from_slice(&ip.octets());
+        },
+        Ok(IpAddr::V6(ip)) => {
+            request.push(ATYP_IPV6);
+            request.extend_from_slice(&ip.octets());
+        },
+        Err(_) => {
+            if host.is_empty() || host.len() > 255 {
+                anyhow::bail!("invalid host name {:?}", host)
+            }
+            request.push(ATYP_DOMAIN);
+            request.push(host.len() as u8);
+            request.extend_from_slice(host.as_bytes());
+        },
+    }
+    request.extend_from_slice(&port.to_be_bytes());
+    stream.write_all(&request)?;
+
+    let mut reply = [0u8; 4];
+    stream.read_exact(&mut reply)?;
+    if reply[0] != VERSION {
+        anyhow::bail!("proxy answered with version {}, not socks5", reply[0])
+    }
+    if reply[1] != 0 {
+        anyhow::bail!("proxy refused the connection: {}", reply_message(reply[1]))
+    }
+    // the address the proxy bound for us is of no use, but it has to be read
+    let bound_len = match reply[3] {
+        ATYP_IPV4 => 4,
+        ATYP_IPV6 => 16,
+        ATYP_DOMAIN => {
+            let mut len = [0u8; 1];
+            stream.read_exact(&mut len)?;
+            len[0] as usize
+        },
+        atyp => anyhow::bail!("proxy answered with unknown address type {}", atyp),
+    };
+    let mut bound = vec![0u8; bound_len + 2];
+    stream.read_exact(&mut bound)?;
+    debug!("socks5 proxy connected to {}:{}", host, port);
+    Ok(())
+}
+
+// username/password authentication as in RFC 1929
+fn authenticate<S: Read + Write>(
+    stream: &mut S,
+    user: &str,
+    pass: &str,
+) -> anyhow::Result<()> {
+    if user.is_empty() || user.len() > 255 || pass.len() > 255 {
+        anyhow::bail!("proxy username and password must be 1 to 255 bytes")
+    }
+    let mut request = vec![USER_PASS_VERSION, user.len() as u8];
+    request.extend_from_slice(user.as_bytes());
+    request.push(pass.len() as u8);
+    request.extend_from_slice(pass.as_bytes());
+    stream.write_all(&request)?;
+
+    let mut reply = [0u8; 2];
+    stream.read_exact(&mut reply)?;
+    if reply[1] != 0 {
+        anyhow::bail!("proxy rejected username/password")
+    }
+    Ok(())
+}
+
+fn split_host_port(address: &str) -> anyhow::Result<(&str, u16)> {
+    let Some((host, port)) = address.rsplit_once(':') else {
+        anyhow::bail!("missing port in address {}", address)
+    };
+    let port = port.parse().with_context(|| format!("invalid port in address {}", address))?;
+    // ipv6 addresses come in brackets
+    let host = host.trim_start_matches('[').trim_end_matches(']');
+    Ok((host, port))
+}
+
+fn reply_message(code: u8) -> &'static str {
+    match code {
+        1 => "general failure",
+        2 => "connection not allowed by ruleset",
+        3 => "network unreachable",
+        4 => "host unreachable",
+        5 => "connection refused",
+        6 => "TTL expired",
+        7 => "command not supported",
+        8 => "address type not supported",
+        _ => "unknown error",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::TcpListener;
+    use std::thread::{self, JoinHandle};
+
+    use super::*;
+
+    // proxy runs serve as the proxy end of a connection on localhost, the other end is returned
+    fn proxy<F>(serve: F) -> (TcpStream, JoinHandle<()>)
+    where
+        F: FnOnce(&mut TcpStream) + Send + 'static,
+    {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let handle = thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            stream.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+            serve(&mut stream);
+        });
+        let stream = TcpStream::connect(addr).unwrap();
+        stream.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+        (stream, handle)
+    }
+
+    fn expect(
+        stream: &mut TcpStream,
+        want: &[u8],
+    ) {
+        let mut got = vec![0u8; want.len()];
+        stream.read_exact(&mut got).unwrap();
+        assert_eq!(got, want);
+    }
+
+    fn connected(stream: &mut TcpStream) {
+        stream.write_all(&[VERSION, 0, 0, ATYP_IPV4, 10, 0, 0, 1, 0x1f, 0x90]).unwrap();
+    }
+
+    #[test]
+    fn no_auth() {
+        let (mut stream, handle) = proxy(|s| {
+            expect(s, &[VERSION, 1, NO_AUTH]);
+            s.write_all(&[VERSION, NO_AUTH]).unwrap();
+            expect(s, &[VERSION, CMD_CONNECT, 0, ATYP_IPV4, 127, 0, 0, 1, 0x23, 0x29]);
+            connected(s);
+        });
+        handshake(&mut stream, None, "127.0.0.1", 9001).unwrap();
+        handle.join().unwrap();
+    }
+
+    #[test]
+    fn user_pass() {
+        let (mut stream, handle) = proxy(|s| {
+            expect(s, &[VERSION, 2, NO_AUTH, USER_PASS_AUTH]);
+            s.write_all(&[VERSION, USER_PASS_AUTH]).unwrap();
+            expect(s, b"\x01\x05alice\x06secret");
+            s.write_all(&[USER_PASS_VERSION, 0]).unwrap();
+            expect(s, &[VERSION, CMD_CONNECT, 0, ATYP_IPV4, 127, 0, 0, 1, 0x23, 0x29]);
+            connected(s);
+        });
+        handshake(&mut stream, Some(("alice", "secret")), "127.0.0.1", 9001).unwrap();
+        handle.join().unwrap();
+    }
+
+    #[test]
+    fn user_pass_rejected() {
+        let (mut stream, handle) = proxy(|s| {
+            expect(s, &[VERSION, 2, NO_AUTH, USER_PASS_AUTH]);
+            s.write_all(&[VERSION, USER_PASS_AUTH]).unwrap();
+            expect(s, b"\x01\x05alice\x05wrong");
+            s.write_all(&[USER_PASS_VERSION, 1]).unwrap();
+        });
+        let err = handshake(&mut stream, Some(("alice", "wrong")), "127.0.0.1", 9001).unwrap_err();
+        assert_eq!(err.to_string(), "proxy rejected username/password");
+        handle.join().unwrap();
+    }
+
+    #[test]
+    fn domain_is_resolved_by_the_proxy() {
+        let (mut stream, handle) = proxy(|s| {
+            expect(s, &[VERSION, 1, NO_AUTH]);
+            s.write_all(&[VERSION, NO_AUTH]).unwrap();
+            expect(s, b"\x05\x01\x00\x03\x10croc.example.com\x23\x29");
+            // the bound address can be a name too
+            s.write_all(b"\x05\x00\x00\x03\x05proxy\x1f\x90").unwrap();
+        });
+        handshake(&mut stream, None, "croc.example.com", 9001).unwrap();
+        handle.join().unwrap();
+    }
+
+    #[test]
+    fn connection_refused() {
+        let (mut stream, handle) = proxy(|s| {
+            expect(s, &[VERSION, 1, NO_AUTH]);
+            s.write_all(&[VERSION, NO_AUTH]).unwrap();
+            expect(s, &[VERSION, CMD_CONNECT, 0, ATYP_IPV4, 127, 0, 0, 1, 0x23, 0x29]);
+            s.write_all(&[VERSION, 5, 0, ATYP_IPV4]).unwrap();
+        });
+        let err = handshake(&mut stream, None, "127.0.0.1", 9001).unwrap_err();
+        assert_eq!(err.to_string(), "proxy refused the connection: connection refused");
+        handle.join().unwrap();
+    }
+}
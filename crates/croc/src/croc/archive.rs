@@ -0,0 +1,235 @@
+use std::collections::BTreeMap;
+use std::fs::{self, File};
+use std::io::{BufWriter, Read};
+use std::os::unix::fs::PermissionsExt;
+use std::path::{Path, PathBuf};
+
+use anyhow::Context;
+use tracing::debug;
+use zip::write::FileOptions;
+use zip::{CompressionMethod, ZipArchive, ZipWriter};
+
+use super::FileInfo;
+
+// the file type bits of a unix mode, zip keeps them in the external attributes
+const S_IFMT: u32 = 0o170000;
+const S_IFLNK: u32 = 0o120000;
+
+// ZipFiles streams the files and empty folders found in a folder into the archive dest,
+// entries are named after their remote path so the folder itself is the top entry
+pub fn zip_files(
+    dest: &Path,
+    files: &[FileInfo],
+    empty_folders: &[FileInfo],
+) -> anyhow::Result<()> {
+    let f = File::create(dest).with_context(|| format!("could not create {:?}", dest))?;
+    let mut writer = ZipWriter::new(BufWriter::new(f));
+    let options = FileOptions::default().compression_method(CompressionMethod::Deflated);
+
+    // every folder on the way to an entry gets one of its own, to keep its permissions
+    let mut folders = BTreeMap::new();
+    for fi in files.iter().chain(empty_folders) {
+        let mut entry = PathBuf::from(entry_folder(&fi.folder_remote));
+        let mut source = PathBuf::from(&fi.folder_source);
+        while entry.file_name().is_some() {
+            folders.entry(entry.clone()).or_insert_with(|| source.clone());
+            entry.pop();
+            source.pop();
+        }
+    }
+    for (entry, source) in &folders {
+        let mode = fs::metadata(source)?.permissions().mode();
+        writer.add_directory(
+            format!("{}/", entry.display()),
+            options.unix_permissions(mode & 0o7777),
+        )?;
+    }
+    for folder in empty_folders {
+        let name = format!("{}{}/", entry_folder(&folder.folder_remote), folder.name);
+        writer.add_directory(name, options.unix_permissions(folder.mode & 0o7777))?;
+    }
+    for fi in files {
+        let name = format!("{}{}", entry_folder(&fi.folder_remote), fi.name);
+        debug!("zipping {}", name);
+        let options = options.unix_permissions(fi.mode & 0o7777);
+        if !fi.symlink_target.is_empty() {
+            writer.add_symlink(name, &fi.symlink_target, options)?;
+            continue;
+        }
+        writer.start_file(name, options.large_file(fi.size >= u32::MAX as u64))?;
+        let mut source = File::open(Path::new(&fi.folder_source).join(&fi.name))?;
+        std::io::copy(&mut source, &mut writer)?;
+    }
+    writer.finish()?;
+    Ok(())
+}
+
+// Unzip extracts the archive into dest and returns the top level entries it created.
+// Entries that would end up outside of dest are refused, as are entries below a symlink.
+pub fn unzip(
+    archive: &Path,
+    dest: &Path,
+) -> anyhow::Result<Vec<PathBuf>> {
+    let f = File::open(archive).with_context(|| format!("could not open {:?}", archive))?;
+    let mut zip = ZipArchive::new(f)?;
+    let mut top_level = vec![];
+    // folders get their permissions last, a read-only one must not keep out its own files
+    let mut folder_modes = vec![];
+    for i in 0..zip.len() {
+        let mut entry = zip.by_index(i)?;
+        let Some(name) = entry.enclosed_name().map(Path::to_path_buf) else {
+            anyhow::bail!("refusing to extract {} outside of {}", entry.name(), dest.display())
+        };
+        check_no_symlink(dest, &name)?;
+        let path = dest.join(&name);
+        if let Some(first) = name.components().next() {
+            let first = dest.join(first);
+            if !top_level.contains(&first) {
+                top_level.push(first);
+            }
+        }
+
+        if entry.is_dir() {
+            fs::create_dir_all(&path)?;
+            if let Some(mode) = entry.unix_mode() {
+                folder_modes.push((path, mode));
+            }
+            continue;
+        }
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        if fs::symlink_metadata(&path).is_ok() {
+            fs::remove_file(&path)?;
+        }
+        let mode = entry.unix_mode();
+        if mode.is_some_and(|x| x & S_IFMT == S_IFLNK) {
+            let mut target = String::new();
+            entry.read_to_string(&mut target)?;
+            debug!("creating symlink {} -> {}", path.display(), target);
+            std::os::unix::fs::symlink(&target, &path)?;
+            continue;
+        }
+        debug!("extracting {}", path.display());
+        let mut out = File::create(&path)?;
+        std::io::copy(&mut entry, &mut out)?;
+        if let Some(mode) = mode {
+            fs::set_permissions(&path, fs::Permissions::from_mode(mode & 0o7777))?;
+        }
+    }
+    for (path, mode) in folder_modes.iter().rev() {
+        fs::set_permissions(path, fs::Permissions::from_mode(mode & 0o7777))?;
+    }
+    Ok(top_level)
+}
+
+// "./" is the root of the transfer, it has no place in the archive
+fn entry_folder(folder_remote: &str) -> &str {
+    folder_remote.strip_prefix("./").unwrap_or(folder_remote)
+}
+
+// CheckNoSymlink makes sure a symlink created earlier, by an archive or the transfer itself,
+// does not take the entry name outside of dest
+pub fn check_no_symlink(
+    dest: &Path,
+    name: &Path,
+) -> anyhow::Result<()> {
+    let mut path = dest.to_path_buf();
+    let Some(parent) = name.parent() else {
+        return Ok(());
+    };
+    for component in parent.components() {
+        path.push(component);
+        if fs::symlink_metadata(&path).is_ok_and(|x| x.is_symlink()) {
+            anyhow::bail!(
+                "refusing to write {} through the symlink {}",
+                name.display(),
+                path.display()
+            )
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Write;
+
+    use super::*;
+
+    // scratch is an empty folder of its own for every test
+    fn scratch(name: &str) -> PathBuf {
+        let dir =
+            std::env::temp_dir().join(format!("croc-archive-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    // archive writes a zip with the files, folders and symlinks in entries,
+    // folders end with "/" and symlinks start with "->"
+    fn archive(
+        dir: &Path,
+        entries: &[(&str, &str)],
+    ) -> PathBuf {
+        let path = dir.join("test.zip");
+        let mut writer = ZipWriter::new(File::create(&path).unwrap());
+        let options = FileOptions::default().unix_permissions(0o640);
+        for (name, content) in entries {
+            match content.strip_prefix("->") {
+                _ if name.ends_with('/') => {
+                    writer.add_directory(*name, options.unix_permissions(0o750)).unwrap()
+                },
+                Some(target) => writer.add_symlink(*name, target, options).unwrap(),
+                None => {
+                    writer.start_file(*name, options).unwrap();
+                    writer.write_all(content.as_bytes()).unwrap();
+                },
+            }
+        }
+        writer.finish().unwrap();
+        path
+    }
+
+    #[test]
+    fn unzip_extracts_into_dest() {
+        let dir = scratch("extract");
+        let zip = archive(&dir, &[("fold/sub/", ""), ("fold/a.txt", "a"), ("fold/sub/b.txt", "b")]);
+        let dest = dir.join("dest");
+        let top_level = unzip(&zip, &dest).unwrap();
+        assert_eq!(top_level, vec![dest.join("fold")]);
+        assert_eq!(fs::read_to_string(dest.join("fold/sub/b.txt")).unwrap(), "b");
+        let mode = fs::metadata(dest.join("fold/a.txt")).unwrap().permissions().mode();
+        assert_eq!(mode & 0o7777, 0o640);
+        let mode = fs::metadata(dest.join("fold/sub")).unwrap().permissions().mode();
+        assert_eq!(mode & 0o7777, 0o750);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn unzip_refuses_entries_outside_of_dest() {
+        let dir = scratch("slip");
+        let dest = dir.join("dest");
+        for name in ["../evil.txt", "fold/../../evil.txt", "/tmp/evil.txt"] {
+            let zip = archive(&dir, &[(name, "evil")]);
+            let err = unzip(&zip, &dest).unwrap_err();
+            assert!(err.to_string().starts_with("refusing to extract"), "{}: {}", name, err);
+        }
+        assert!(!dir.join("evil.txt").exists());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn unzip_refuses_entries_below_a_symlink() {
+        let dir = scratch("symlink");
+        let outside = dir.join("outside");
+        fs::create_dir_all(&outside).unwrap();
+        let target = format!("->{}", outside.display());
+        let zip = archive(&dir, &[("fold/link", &target), ("fold/link/evil.txt", "evil")]);
+        let dest = dir.join("dest");
+        let err = unzip(&zip, &dest).unwrap_err();
+        assert!(err.to_string().contains("through the symlink"), "{}", err);
+        assert!(!outside.join("evil.txt").exists());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
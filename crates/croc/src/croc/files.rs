@@ -0,0 +1,330 @@
+use std::fs;
+use std::os::unix::fs::PermissionsExt;
+use std::path::{Path, PathBuf};
+use std::time::UNIX_EPOCH;
+
+use anyhow::Context;
+use ignore::gitignore::{Gitignore, GitignoreBuilder};
+use ignore::Match;
+use tracing::{debug, warn};
+
+use super::{archive, FileInfo};
+
+// This function retrieves the important file information
+// for every file that will be transferred
+pub fn get_files_info(
+    fnames: &[String],
+    zip_folder: bool,
+    ignore_git: bool,
+) -> anyhow::Result<(Vec<FileInfo>, Vec<FileInfo>, usize)> {
+    // fnames: the relative/absolute paths of files/folders that will be transferred
+    let mut paths: Vec<String> = vec![];
+    let mut walker = Walker::default();
+    for fname in fnames {
+        // "-" sends whatever comes in on stdin
+        if fname == "-" {
+            if fnames.len() > 1 {
+                anyhow::bail!("stdin can only be sent on its own")
+            }
+            return Ok((vec![stdin_file_info()], vec![], 0));
+        }
+        // Support wildcard
+        if fname.contains(['*', '?', '[']) {
+            let matches = glob::glob(fname).with_context(|| format!("bad pattern {}", fname))?;
+            let mut n = 0;
+            for m in matches {
+                paths.push(m?.display().to_string());
+                n += 1;
+            }
+            if n == 0 {
+                anyhow::bail!("no files match {}", fname)
+            }
+        } else {
+            paths.push(fname.clone());
+        }
+    }
+
+    for path in &paths {
+        let stat =
+            fs::symlink_metadata(path).with_context(|| format!("could not stat {}", path))?;
+        let abs_path = if stat.is_dir() {
+            fs::canonicalize(path)?
+        } else {
+            std::env::current_dir()?.join(path)
+        };
+        walker.git = if ignore_git { GitIgnore::new(&abs_path) } else { None };
+        if walker.is_ignored(&abs_path, stat.is_dir()) {
+            continue;
+        }
+        if stat.is_dir() && zip_folder {
+            let fi = walker.zip(&abs_path)?;
+            walker.files_info.push(fi);
+        } else if stat.is_dir() {
+            // the folder itself is sent, so remote paths start with its name
+            let base = abs_path.parent().unwrap_or(Path::new("/")).to_path_buf();
+            walker.walk(&abs_path, &base)?;
+        } else {
+            walker.files_info.push(file_info(&abs_path, &stat, "./".into())?);
+        }
+    }
+
+    if walker.skipped_files > 0 || walker.skipped_folders > 0 {
+        eprintln!(
+            "Skipped {} files and {} folders ignored by git",
+            walker.skipped_files, walker.skipped_folders
+        );
+    }
+    Ok((walker.files_info, walker.empty_folders, walker.total_number_folders))
+}
+
+// Walker collects what is found under the folders that will be transferred
+#[derive(Default)]
+struct Walker {
+    files_info: Vec<FileInfo>,
+    empty_folders: Vec<FileInfo>,
+    total_number_folders: usize,
+    // the rules of the git repository being walked, when --git is set
+    git: Option<GitIgnore>,
+    skipped_files: usize,
+    skipped_folders: usize,
+    // folders zipped so far, each archive gets a temporary folder of its own
+    zipped: usize,
+}
+
+impl Walker {
+    fn walk(
+        &mut self,
+        dir: &Path,
+        base: &Path,
+    ) -> anyhow::Result<()> {
+        self.total_number_folders += 1;
+        let mut entries: Vec<PathBuf> = fs::read_dir(dir)
+            .with_context(|| format!("could not read {}", dir.display()))?
+            .map(|x| x.map(|y| y.path()))
+            .collect::<Result<_, _>>()?;
+        entries.sort();
+
+        if entries.is_empty() {
+            debug!("empty folder {}", dir.display());
+            self.empty_folders.push(FileInfo {
+                name: file_name(dir),
+                folder_remote: remote_folder(dir, base),
+                folder_source: dir.parent().unwrap_or(Path::new("/")).display().to_string(),
+                mode: fs::metadata(dir)?.permissions().mode(),
+                ..Default::default()
+            });
+            return Ok(());
+        }
+
+        if let Some(git) = self.git.as_mut() {
+            git.push(dir);
+        }
+        for entry in entries {
+            let stat = fs::symlink_metadata(&entry)?;
+            if self.is_ignored(&entry, stat.is_dir()) {
+                continue;
+            }
+            if stat.is_dir() {
+                self.walk(&entry, base)?;
+            } else {
+                self.files_info.push(file_info(&entry, &stat, remote_folder(&entry, base))?);
+            }
+        }
+        if let Some(git) = self.git.as_mut() {
+            git.pop();
+        }
+        Ok(())
+    }
+
+    // zip walks a folder on its own and streams what it finds into an archive
+    // in the temporary folder, the archive is sent in place of the folder
+    fn zip(
+        &mut self,
+        dir: &Path,
+    ) -> anyhow::Result<FileInfo> {
+        let mut folder = Walker {
+            git: self.git.take(),
+            ..Default::default()
+        };
+        let base = dir.parent().unwrap_or(Path::new("/")).to_path_buf();
+        folder.walk(dir, &base)?;
+        self.skipped_files += folder.skipped_files;
+        self.skipped_folders += folder.skipped_folders;
+
+        // folders of the same name are zipped to the same name, but not to the same file
+        let temp_dir =
+            std::env::temp_dir().join(format!("croc-{}-{}", std::process::id(), self.zipped));
+        self.zipped += 1;
+        fs::create_dir_all(&temp_dir)?;
+        let dest = temp_dir.join(file_name(dir) + ".zip");
+        debug!("zipping {} to {}", dir.display(), dest.display());
+        archive::zip_files(&dest, &folder.files_info, &folder.empty_folders)?;
+
+        let stat = fs::metadata(&dest)?;
+        let mut fi = file_info(&dest, &stat, "./".into())?;
+        fi.temp_file = true;
+        Ok(fi)
+    }
+
+    // is_ignored also counts what it skips, so it can be reported at the end
+    fn is_ignored(
+        &mut self,
+        path: &Path,
+        is_dir: bool,
+    ) -> bool {
+        let Some(git) = &self.git else {
+            return false;
+        };
+        // the repository itself is never sent, nor counted as skipped
+        if is_dir && path.file_name().is_some_and(|x| x == ".git") {
+            return true;
+        }
+        if !git.is_ignored(path, is_dir) {
+            return false;
+        }
+        debug!("ignoring {}", path.display());
+        if is_dir {
+            self.skipped_folders += 1;
+        } else {
+            self.skipped_files += 1;
+        }
+        true
+    }
+}
+
+// GitIgnore decides like git which paths of a repository are ignored:
+// the .gitignore closest to a path wins over the ones above it,
+// and .git/info/exclude is only looked at when none of them matches
+struct GitIgnore {
+    // one matcher per folder, from the root of the repository to the current folder
+    stack: Vec<Gitignore>,
+    exclude: Gitignore,
+}
+
+impl GitIgnore {
+    // find the repository path lives in and load the rules that apply above it,
+    // returns None when path is not inside a git repository
+    fn new(path: &Path) -> Option<GitIgnore> {
+        let root = path.ancestors().find(|x| x.join(".git").exists())?;
+        let mut git = GitIgnore {
+            stack: vec![],
+            exclude: load_gitignore(root, &root.join(".git").join("info").join("exclude")),
+        };
+        let mut folders: Vec<&Path> = match path.parent() {
+            None => vec![],
+            Some(x) => x.ancestors().take_while(|y| y.starts_with(root)).collect(),
+        };
+        folders.reverse();
+        for folder in folders {
+            git.push(folder);
+        }
+        Some(git)
+    }
+
+    fn push(
+        &mut self,
+        dir: &Path,
+    ) {
+        self.stack.push(load_gitignore(dir, &dir.join(".gitignore")));
+    }
+
+    fn pop(&mut self) {
+        self.stack.pop();
+    }
+
+    fn is_ignored(
+        &self,
+        path: &Path,
+        is_dir: bool,
+    ) -> bool {
+        for matcher in self.stack.iter().rev().chain([&self.exclude]) {
+            match matcher.matched(path, is_dir) {
+                Match::None => continue,
+                Match::Ignore(_) => return true,
+                // a "!pattern" brings back what a broader rule ignored
+                Match::Whitelist(_) => return false,
+            }
+        }
+        false
+    }
+}
+
+// load the rules of one ignore file, patterns in it are relative to root
+fn load_gitignore(
+    root: &Path,
+    file: &Path,
+) -> Gitignore {
+    if !file.is_file() {
+        return Gitignore::empty();
+    }
+    let mut builder = GitignoreBuilder::new(root);
+    if let Some(e) = builder.add(file) {
+        warn!("problem reading {}: {}", file.display(), e);
+    }
+    builder.build().unwrap_or_else(|e| {
+        warn!("could not use {}: {}", file.display(), e);
+        Gitignore::empty()
+    })
+}
+
+fn file_info(
+    path: &Path,
+    stat: &fs::Metadata,
+    folder_remote: String,
+) -> anyhow::Result<FileInfo> {
+    let symlink_target = if stat.is_symlink() {
+        fs::read_link(path)?.display().to_string()
+    } else {
+        String::new()
+    };
+    let mod_time = match stat.modified()?.duration_since(UNIX_EPOCH) {
+        Ok(x) => x.as_secs() as i64,
+        Err(e) => -(e.duration().as_secs() as i64),
+    };
+    Ok(FileInfo {
+        name: file_name(path),
+        folder_remote,
+        folder_source: path.parent().unwrap_or(Path::new("/")).display().to_string(),
+        // a symlink is recreated by the recipient, nothing to send
+        size: if symlink_target.is_empty() { stat.len() } else { 0 },
+        mod_time,
+        mode: stat.permissions().mode(),
+        symlink_target,
+        ..Default::default()
+    })
+}
+
+fn stdin_file_info() -> FileInfo {
+    let mod_time = std::time::SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |x| x.as_secs() as i64);
+    FileInfo {
+        name: "stdin".into(),
+        folder_remote: "./".into(),
+        mod_time,
+        mode: 0o644,
+        stream: true,
+        ..Default::default()
+    }
+}
+
+fn file_name(path: &Path) -> String {
+    path.file_name().map(|x| x.to_string_lossy().into_owned()).unwrap_or_default()
+}
+
+// the folder of path relative to base, in the form "a/b/" that the recipient expects
+fn remote_folder(
+    path: &Path,
+    base: &Path,
+) -> String {
+    let parent = path.parent().unwrap_or(base);
+    let relative = parent.strip_prefix(base).unwrap_or(parent);
+    let parts: Vec<String> = relative
+        .components()
+        .map(|x| x.as_os_str().to_string_lossy().into_owned())
+        .collect();
+    if parts.is_empty() {
+        return "./".into();
+    }
+    parts.join("/") + "/"
+}
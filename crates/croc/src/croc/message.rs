@@ -0,0 +1,69 @@
+use serde::{Deserialize, Serialize};
+use tracing::trace;
+
+use super::{RemoteFileRequest, SenderInfo};
+use crate::{comm, crypt};
+
+// Message is the structure of the messages exchanged between sender and recipient
+#[derive(Debug, Serialize, Deserialize)]
+pub enum Message {
+    // PAKE bytes of one side, the sender adds the salt used to derive the key
+    Pake { bytes: Vec<u8>, salt: Vec<u8> },
+    FileInfo(SenderInfo),
+    RecipientReady(RemoteFileRequest),
+    // a piece of the current file, starting at position
+    Chunk { position: u64, data: Vec<u8> },
+    // the end of a stream of unknown length, with the number of bytes that were sent
+    EndOfStream { size: u64 },
+    Error(String),
+    Finished,
+}
+
+// Encode serializes a message and encrypts it, unless there is no key yet
+pub fn encode(
+    key: &[u8],
+    m: &Message,
+) -> anyhow::Result<Vec<u8>> {
+    let b = bincode::serialize(m)?;
+    if key.is_empty() {
+        return Ok(b);
+    }
+    crypt::encrypt(&b, key)
+}
+
+// Decode will decrypt a message, unless there is no key yet, and deserialize it
+pub fn decode(
+    key: &[u8],
+    data: &[u8],
+) -> anyhow::Result<Message> {
+    if key.is_empty() {
+        return Ok(bincode::deserialize(data)?);
+    }
+    let b = crypt::decrypt(data, key)?;
+    Ok(bincode::deserialize(&b)?)
+}
+
+pub fn send(
+    c: &mut comm::Comm,
+    key: &[u8],
+    m: &Message,
+) -> anyhow::Result<()> {
+    let data = encode(key, m)?;
+    c.send(&data)
+}
+
+// Receive the next message, skipping the keep-alive bytes the relay sends
+// while a room is waiting for its second member
+pub fn receive(
+    c: &mut comm::Comm,
+    key: &[u8],
+) -> anyhow::Result<Message> {
+    loop {
+        let data = c.receive()?;
+        if data == [1u8] {
+            trace!("got ping");
+            continue;
+        }
+        return decode(key, &data);
+    }
+}
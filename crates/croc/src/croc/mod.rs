@@ -0,0 +1,245 @@
+use std::collections::BTreeSet;
+use std::ops::Not;
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use tracing::debug;
+
+use crate::throttle::Throttle;
+use crate::{comm, hash, model, tcp, utils};
+
+mod archive;
+mod files;
+mod message;
+mod partial;
+mod receive;
+mod send;
+
+pub use files::get_files_info;
+
+// identities of both sides of the PAKE between sender and recipient
+const PAKE_ID_RECIPIENT: &[u8] = b"croc-recipient";
+const PAKE_ID_SENDER: &[u8] = b"croc-sender";
+
+// MULTIPLEX_BLOCK_SIZE is how much of a file goes over one connection before the next one takes over
+const MULTIPLEX_BLOCK_SIZE: u64 = 1024 * 1024;
+
+// ROOM_PIN_LENGTH is how much of a code of your own goes into the room, like the pin of a generated one
+const ROOM_PIN_LENGTH: usize = 4;
+
+// Options specifies user specific options
+#[derive(Debug)]
+pub struct Options {
+    pub is_sender: bool,
+    pub shared_secret: String,
+    pub relay_address: String,
+    pub relay_address6: String,
+    pub relay_ports: Vec<String>,
+    pub relay_password: String,
+    pub zip_folder: bool,
+    pub git_ignore: bool,
+    pub disable_local: bool,
+    pub only_local: bool,
+    pub no_multi: bool,
+    pub hash_algorithm: hash::Algorithm,
+    // text sent in place of files
+    pub text: Option<String>,
+    // write the received file to stdout instead of the current folder
+    pub stdout: bool,
+    pub ip: String,
+    // bytes per second over all connections together, only for sending
+    pub throttle: Option<u64>,
+}
+
+// FileInfo registers the information about the file
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct FileInfo {
+    pub name: String,
+    pub folder_remote: String,
+    #[serde(skip)]
+    pub folder_source: String,
+    pub size: u64,
+    // seconds since the unix epoch
+    pub mod_time: i64,
+    pub mode: u32,
+    pub symlink_target: String,
+    pub hash: Vec<u8>,
+    // a zip of a folder made for the transfer, the recipient extracts it
+    pub temp_file: bool,
+    // data read from stdin, the size is only known once the stream ends
+    pub stream: bool,
+}
+
+// SenderInfo is what the sender tells the recipient about the transfer
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct SenderInfo {
+    pub files_to_transfer: Vec<FileInfo>,
+    pub empty_folders_to_transfer: Vec<FileInfo>,
+    pub total_number_folders: usize,
+    // the file data goes over the main connection only
+    pub no_multiplexing: bool,
+    // how the hashes of the files were made
+    pub hash_algorithm: hash::Algorithm,
+    // a snippet of text, the recipient prints it instead of writing a file
+    pub text: Option<String>,
+}
+
+// RemoteFileRequest requests the byte ranges [start, end) of a file
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct RemoteFileRequest {
+    pub files_to_transfer_current_num: usize,
+    pub current_file_chunk_ranges: Vec<(u64, u64)>,
+}
+
+// Client holds the state of the croc transfer
+pub struct Client {
+    options: Options,
+    room_name: String,
+    // key derived from the PAKE between sender and recipient
+    key: Vec<u8>,
+
+    files_to_transfer: Vec<FileInfo>,
+    empty_folders_to_transfer: Vec<FileInfo>,
+    total_number_folders: usize,
+
+    // steps involved in forming relationship
+    step1_channel_secured: bool,
+    files_has_finished: BTreeSet<usize>,
+
+    // shared by all connections of the transfer
+    throttle: Option<Throttle>,
+}
+
+// New establishes a new connection for transferring files between two instances.
+pub fn new(ops: Options) -> anyhow::Result<Client> {
+    if ops.shared_secret.len() < model::MIN_CODE_LENGTH {
+        anyhow::bail!("need shared secret >= {} characters", model::MIN_CODE_LENGTH)
+    }
+    let room_name = room_name(&ops.shared_secret);
+    let clt = Client {
+        room_name,
+        key: vec![],
+        files_to_transfer: vec![],
+        empty_folders_to_transfer: vec![],
+        total_number_folders: 0,
+        step1_channel_secured: false,
+        files_has_finished: BTreeSet::new(),
+        throttle: ops.throttle.map(Throttle::new),
+        options: ops,
+    };
+    Ok(clt)
+}
+
+// RoomName is what both sides tell the relay to find each other.
+// It is a hash of only the pin of the code, all the words stay in the PAKE
+// so the relay can't brute force them.
+fn room_name(shared_secret: &str) -> String {
+    let public = match shared_secret.split_once('-') {
+        Some((pin, _)) if !pin.is_empty() && pin.bytes().all(|x| x.is_ascii_digit()) => pin,
+        // not a generated code phrase, its first characters stand in for the pin
+        _ => {
+            let end = shared_secret.char_indices().nth(ROOM_PIN_LENGTH);
+            &shared_secret[..end.map_or(shared_secret.len(), |(i, _)| i)]
+        },
+    };
+    format!("{:x}", Sha256::digest(format!("croc-room-{}", public).as_bytes()))
+}
+
+fn broadcast_on_local_network(
+    only_local: bool,
+    first_reply_port: &str,
+    useipv6: bool,
+) {
+    // if we don't use an external relay, the broadcast messages need to be sent continuously
+    let time_limit = only_local.not().then(|| Duration::from_secs(30));
+    // look for peers first
+    let settings = peerdiscovery::Settings {
+        limit: -1,
+        payload: format!("croc{}", first_reply_port).into_bytes(),
+        delay: Duration::from_millis(20),
+        time_limit,
+        ip_version: if useipv6 {
+            peerdiscovery::IPVersion::V6
+        } else {
+            peerdiscovery::IPVersion::V4
+        },
+        ..Default::default()
+    };
+    match peerdiscovery::discover(&[settings]) {
+        Err(e) => debug!("could not broadcast (ipv6: {}): {:?}", useipv6, e),
+        Ok(discoveries) => debug!("discoveries (ipv6: {}): {}", useipv6, discoveries.len()),
+    }
+}
+
+// try the relays in order and use the first one that lets us in,
+// returns the connection, the address it was made to, the banner and our ip
+fn connect_to_relay(
+    addresses: &[String],
+    password: &str,
+    room: &str,
+) -> anyhow::Result<(comm::Comm, String, String, String)> {
+    let mut last_err = anyhow::anyhow!("no relay address");
+    for address in addresses.iter().filter(|x| !x.is_empty()) {
+        let address = utils::address_with_port(address, model::DEFAULT_PORT);
+        debug!("establishing connection to {}", address);
+        match tcp::connect_to_tcp_server(&address, password, room, Duration::from_secs(5)) {
+            Ok((conn, banner, ipaddr)) => return Ok((conn, address, banner, ipaddr)),
+            Err(e) => {
+                debug!("could not connect to {}: {:?}", address, e);
+                last_err = e;
+            },
+        }
+    }
+    Err(last_err)
+}
+
+// the banner of the relay lists its other ports, they are used for parallel connections
+fn banner_ports(banner: &str) -> Vec<String> {
+    banner
+        .split(',')
+        .filter(|x| x.parse::<u16>().is_ok())
+        .map(String::from)
+        .collect()
+}
+
+// join the same room on the other ports of the relay, one connection per port
+fn connect_to_ports(
+    address: &str,
+    ports: &[String],
+    password: &str,
+    room: &str,
+) -> anyhow::Result<Vec<comm::Comm>> {
+    let host = address.rsplit_once(':').map_or(address, |(x, _)| x);
+    ports
+        .iter()
+        .map(|port| {
+            let address = format!("{}:{}", host, port);
+            debug!("establishing parallel connection to {}", address);
+            let (conn, _, _) =
+                tcp::connect_to_tcp_server(&address, password, room, Duration::from_secs(5))?;
+            Ok(conn)
+        })
+        .collect()
+}
+
+// split the requested ranges of a file in blocks and deal them out over n connections,
+// both sides do the same so the recipient knows what arrives on each connection
+fn split_ranges(
+    ranges: &[(u64, u64)],
+    n: usize,
+) -> Vec<Vec<(u64, u64)>> {
+    let mut parts = vec![vec![]; n.max(1)];
+    let mut i = 0;
+    for &(start, end) in ranges {
+        let mut position = start;
+        while position < end {
+            let block_end = end.min(position + MULTIPLEX_BLOCK_SIZE);
+            let num = parts.len();
+            parts[i % num].push((position, block_end));
+            position = block_end;
+            i += 1;
+        }
+    }
+    parts
+}
@@ -0,0 +1,199 @@
+use std::ffi::OsString;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+use serde::{Deserialize, Serialize};
+use tracing::debug;
+
+use super::FileInfo;
+
+// how often the record is written to disk while chunks come in,
+// whatever arrived since the last write is simply asked for again
+const SAVE_INTERVAL: Duration = Duration::from_secs(1);
+
+// ChunkRecord remembers which parts of a partial file were received,
+// so an interrupted transfer only asks the sender for what is missing
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct ChunkRecord {
+    // the version of the file the chunks belong to
+    hash: Vec<u8>,
+    size: u64,
+    // received ranges [start, end), sorted and merged
+    ranges: Vec<(u64, u64)>,
+    #[serde(skip)]
+    record_path: PathBuf,
+    #[serde(skip)]
+    last_save: Option<Instant>,
+}
+
+impl ChunkRecord {
+    // Load picks up what a previous attempt left of path,
+    // anything that does not belong to the file the sender has now starts over
+    pub fn load(
+        path: &Path,
+        fi: &FileInfo,
+    ) -> ChunkRecord {
+        let record_path = with_suffix(path, ".croc-chunks");
+        let previous = fs::read(&record_path)
+            .ok()
+            .and_then(|b| bincode::deserialize::<ChunkRecord>(&b).ok())
+            .filter(|x| x.hash == fi.hash && x.size == fi.size && partial_path(path).is_file());
+        match previous {
+            Some(mut record) => {
+                debug!("resuming {} with {:?}", path.display(), record.ranges);
+                record.record_path = record_path;
+                record
+            },
+            None => ChunkRecord {
+                hash: fi.hash.clone(),
+                size: fi.size,
+                ranges: vec![],
+                record_path,
+                last_save: None,
+            },
+        }
+    }
+
+    // Received is the number of bytes already on disk
+    pub fn received(&self) -> u64 {
+        self.ranges.iter().map(|(s, e)| e - s).sum()
+    }
+
+    // Missing returns the ranges that still have to be requested from the sender
+    pub fn missing(&self) -> Vec<(u64, u64)> {
+        let mut missing = vec![];
+        let mut position = 0;
+        for &(start, end) in &self.ranges {
+            if start > position {
+                missing.push((position, start));
+            }
+            position = position.max(end);
+        }
+        if position < self.size {
+            missing.push((position, self.size));
+        }
+        missing
+    }
+
+    // Add marks [start, end) as written to the partial file
+    pub fn add(
+        &mut self,
+        start: u64,
+        end: u64,
+    ) -> anyhow::Result<()> {
+        let i = self.ranges.partition_point(|x| x.0 < start);
+        self.ranges.insert(i, (start, end));
+        let mut merged: Vec<(u64, u64)> = Vec::with_capacity(self.ranges.len());
+        for &(s, e) in &self.ranges {
+            match merged.last_mut() {
+                Some(last) if s <= last.1 => last.1 = last.1.max(e),
+                _ => merged.push((s, e)),
+            }
+        }
+        self.ranges = merged;
+
+        if self.last_save.map_or(true, |x| x.elapsed() >= SAVE_INTERVAL) {
+            self.save()?;
+        }
+        Ok(())
+    }
+
+    pub fn save(&mut self) -> anyhow::Result<()> {
+        fs::write(&self.record_path, bincode::serialize(&self)?)?;
+        self.last_save = Some(Instant::now());
+        Ok(())
+    }
+
+    // Remove forgets the record, once the file is complete or turned out to be bad
+    pub fn remove(&self) {
+        if let Err(e) = fs::remove_file(&self.record_path) {
+            debug!("could not remove {}: {:?}", self.record_path.display(), e);
+        }
+    }
+}
+
+// PartialPath is where a file is written until all of it arrived and it is verified
+pub fn partial_path(path: &Path) -> PathBuf {
+    with_suffix(path, ".croc-partial")
+}
+
+fn with_suffix(
+    path: &Path,
+    suffix: &str,
+) -> PathBuf {
+    let mut name: OsString = path.as_os_str().into();
+    name.push(suffix);
+    name.into()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // scratch is an empty folder of its own for every test
+    fn scratch(name: &str) -> PathBuf {
+        let dir =
+            std::env::temp_dir().join(format!("croc-partial-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn file_info(hash: &[u8]) -> FileInfo {
+        FileInfo {
+            hash: hash.to_vec(),
+            size: 100,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn add_merges_overlapping_and_adjacent_ranges() {
+        let dir = scratch("add");
+        let mut record = ChunkRecord::load(&dir.join("file"), &file_info(b"h"));
+        for (start, end) in [(30, 40), (10, 20), (15, 25)] {
+            record.add(start, end).unwrap();
+        }
+        assert_eq!(record.ranges, vec![(10, 25), (30, 40)]);
+        // touching ranges become one
+        record.add(25, 30).unwrap();
+        assert_eq!(record.ranges, vec![(10, 40)]);
+        record.add(0, 5).unwrap();
+        record.add(12, 18).unwrap();
+        assert_eq!(record.ranges, vec![(0, 5), (10, 40)]);
+        assert_eq!(record.received(), 35);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn missing_is_the_gaps() {
+        let dir = scratch("missing");
+        let mut record = ChunkRecord::load(&dir.join("file"), &file_info(b"h"));
+        assert_eq!(record.missing(), vec![(0, 100)]);
+        record.add(0, 5).unwrap();
+        record.add(10, 40).unwrap();
+        assert_eq!(record.missing(), vec![(5, 10), (40, 100)]);
+        record.add(40, 100).unwrap();
+        record.add(5, 10).unwrap();
+        assert_eq!(record.missing(), vec![]);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn load_resumes_only_the_same_file() {
+        let dir = scratch("load");
+        let path = dir.join("file");
+        let mut record = ChunkRecord::load(&path, &file_info(b"h"));
+        record.add(0, 50).unwrap();
+        record.save().unwrap();
+        fs::write(partial_path(&path), [0u8; 50]).unwrap();
+        assert_eq!(ChunkRecord::load(&path, &file_info(b"h")).ranges, vec![(0, 50)]);
+        // the sender has another version of the file now
+        assert_eq!(ChunkRecord::load(&path, &file_info(b"other")).ranges, vec![]);
+        // the record is of no use without the partial file it describes
+        fs::remove_file(partial_path(&path)).unwrap();
+        assert_eq!(ChunkRecord::load(&path, &file_info(b"h")).ranges, vec![]);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,564 @@
+use std::fs::{self, File, OpenOptions};
+use std::io::Write;
+use std::os::unix::fs::{FileExt, PermissionsExt};
+use std::path::{Component, Path, PathBuf};
+use std::time::Duration;
+
+use parking_lot::Mutex;
+use spake2::{Ed25519Group, Identity, Password, Spake2};
+use tracing::debug;
+
+use super::archive;
+use super::message::{self, Message};
+use super::partial::{partial_path, ChunkRecord};
+use super::{
+    banner_ports, connect_to_ports, connect_to_relay, split_ranges, Client, FileInfo,
+    RemoteFileRequest, SenderInfo, PAKE_ID_RECIPIENT, PAKE_ID_SENDER,
+};
+use crate::progress::Progress;
+use crate::{comm, crypt, hash, tcp, utils};
+
+// ReceiveResult tells what ended up on disk after a transfer
+#[derive(Debug, Default)]
+pub struct ReceiveResult {
+    pub files: Vec<ReceivedFile>,
+    pub empty_folders: Vec<PathBuf>,
+    // the text that was sent instead of files
+    pub text: Option<String>,
+}
+
+// ReceivedFile is a file that was written and verified against the sender's hash
+#[derive(Debug)]
+pub struct ReceivedFile {
+    pub path: PathBuf,
+    pub size: u64,
+    pub hash: Vec<u8>,
+}
+
+impl Client {
+    pub fn receive(&mut self) -> anyhow::Result<ReceiveResult> {
+        let mut stderr = std::io::stderr();
+        stderr.write_all(b"connecting...")?;
+        stderr.flush()?;
+        // recipient will look for peers first
+        // and continue if it doesn't find any within 100 ms
+
+        let mut using_local = false;
+        let mut is_ipset = false;
+        if self.options.only_local || !self.options.ip.is_empty() {
+            self.options.relay_address = "".into();
+            self.options.relay_address6 = "".into();
+        }
+        if !self.options.ip.is_empty() {
+            // check ip version
+            if self.options.ip.matches(":").count() >= 2 {
+                debug!("assume ipv6");
+                self.options.relay_address6 = self.options.ip.clone();
+            }
+            if self.options.ip.contains(".") {
+                debug!("assume ipv4");
+                self.options.relay_address = self.options.ip.clone();
+            }
+            is_ipset = true;
+        }
+
+        if !(self.options.disable_local || is_ipset) {
+            debug!("attempt to discover peers");
+            if let Some(address) = discover_local_sender() {
+                debug!("using local relay of the sender at {}", address);
+                self.options.relay_address = address;
+                self.options.relay_address6 = "".into();
+                using_local = true;
+            }
+        }
+        if self.options.only_local && !using_local && !is_ipset {
+            anyhow::bail!("could not find the sender on the local network")
+        }
+
+        let addresses = [self.options.relay_address6.clone(), self.options.relay_address.clone()];
+        let (conn, address, banner, ipaddr) =
+            connect_to_relay(&addresses, &self.options.relay_password, &self.room_name)?;
+        debug!(banner, ipaddr, using_local, "connected to relay");
+        stderr.write_all(b"\rsecuring channel...")?;
+        stderr.flush()?;
+
+        self.transfer_receive(conn, &address, &banner)
+    }
+
+    fn transfer_receive(
+        &mut self,
+        mut conn: comm::Comm,
+        address: &str,
+        banner: &str,
+    ) -> anyhow::Result<ReceiveResult> {
+        // the recipient starts the PAKE, the sender answers with its part and the salt
+        let (pake, pake_bytes) = Spake2::<Ed25519Group>::start_a(
+            &Password::new(self.options.shared_secret.as_bytes()),
+            &Identity::new(PAKE_ID_RECIPIENT),
+            &Identity::new(PAKE_ID_SENDER),
+        );
+        message::send(
+            &mut conn,
+            &[],
+            &Message::Pake {
+                bytes: pake_bytes,
+                salt: vec![],
+            },
+        )?;
+        let Message::Pake { bytes, salt } = message::receive(&mut conn, &[])? else {
+            anyhow::bail!("expected the sender to answer the PAKE")
+        };
+        let session_key = pake.finish(&bytes).map_err(|e| anyhow::anyhow!("{:?}", e))?;
+        let (key, _) = crypt::new(&session_key, &salt)?;
+        self.key = key;
+        self.step1_channel_secured = true;
+
+        // only the right code gives the key to read the file list
+        let info: SenderInfo = match message::receive(&mut conn, &self.key) {
+            Ok(Message::FileInfo(x)) => x,
+            Ok(_) => anyhow::bail!("unexpected message from sender"),
+            Err(e) => {
+                debug!(error = ?e);
+                anyhow::bail!("could not decrypt the file list, is the code correct?")
+            },
+        };
+        self.files_to_transfer = info.files_to_transfer;
+        self.empty_folders_to_transfer = info.empty_folders_to_transfer;
+        self.total_number_folders = info.total_number_folders;
+        self.options.hash_algorithm = info.hash_algorithm;
+        if let Some(text) = info.text {
+            eprintln!("\rReceiving text ({})", utils::byte_count_decimal(text.len() as u64));
+            message::send(&mut conn, &self.key, &Message::Finished)?;
+            if let Err(e) = message::receive(&mut conn, &self.key) {
+                debug!("sender did not confirm: {:?}", e);
+            }
+            return Ok(ReceiveResult {
+                text: Some(text),
+                ..Default::default()
+            });
+        }
+        self.print_files_to_receive();
+        if self.options.stdout
+            && (self.files_to_transfer.len() != 1 || !self.empty_folders_to_transfer.is_empty())
+        {
+            let e = "only a single file can be written to stdout";
+            message::send(&mut conn, &self.key, &Message::Error(e.into()))?;
+            anyhow::bail!(e)
+        }
+
+        // the sender is joining the rooms on the other ports of the relay as well
+        let mut data_conns = vec![];
+        if !info.no_multiplexing {
+            let ports = banner_ports(banner);
+            data_conns =
+                connect_to_ports(address, &ports, &self.options.relay_password, &self.room_name)?;
+            debug!("receiving over {} connections", data_conns.len());
+        }
+
+        let mut rst = ReceiveResult::default();
+        for folder in &self.empty_folders_to_transfer {
+            let path = local_path(&folder.folder_remote, &folder.name)?;
+            archive::check_no_symlink(Path::new("."), &path)?;
+            fs::create_dir_all(&path)?;
+            rst.empty_folders.push(path);
+        }
+
+        for i in 0..self.files_to_transfer.len() {
+            let fi = self.files_to_transfer[i].clone();
+            let path = local_path(&fi.folder_remote, &fi.name)?;
+            // nothing goes to disk with --stdout, the local path is left alone
+            if !self.options.stdout {
+                // the symlinks of this transfer are created as it goes, never write through one
+                archive::check_no_symlink(Path::new("."), &path)?;
+                if let Some(parent) = path.parent() {
+                    fs::create_dir_all(parent)?;
+                }
+                if !fi.temp_file && fs::symlink_metadata(&path).is_ok_and(|x| x.is_symlink()) {
+                    fs::remove_file(&path)?;
+                }
+            }
+            let (size, hash) = (fi.size, fi.hash.clone());
+            if fi.stream || self.options.stdout {
+                let request = RemoteFileRequest {
+                    files_to_transfer_current_num: i,
+                    current_file_chunk_ranges: vec![(0, size)],
+                };
+                message::send(&mut conn, &self.key, &Message::RecipientReady(request))?;
+                let conns = if data_conns.is_empty() {
+                    std::slice::from_mut(&mut conn)
+                } else {
+                    &mut data_conns[..]
+                };
+                if self.options.stdout {
+                    // there is no file to check afterwards, the bytes are hashed as they go out
+                    let mut stdout = HashingWriter {
+                        writer: std::io::stdout().lock(),
+                        hasher: hash::Hasher::new(self.options.hash_algorithm, size),
+                    };
+                    let size = self.receive_in_order(conns, i, &mut stdout)?;
+                    stdout.flush()?;
+                    // a stream has no hash, its size is only known at the end
+                    if !fi.stream && stdout.hasher.finish()? != hash {
+                        anyhow::bail!("hashes are not equal for {}, try again", fi.name)
+                    }
+                    rst.files.push(ReceivedFile {
+                        path: "-".into(),
+                        size,
+                        hash,
+                    });
+                } else {
+                    let mut file = File::create(&path)?;
+                    let size = self.receive_in_order(conns, i, &mut file)?;
+                    set_file_attributes(&path, &fi)?;
+                    rst.files.push(ReceivedFile { path, size, hash });
+                }
+                self.files_has_finished.insert(i);
+                continue;
+            }
+            if !fi.symlink_target.is_empty() {
+                debug!("creating symlink {} -> {}", path.display(), fi.symlink_target);
+                if fs::symlink_metadata(&path).is_ok() {
+                    fs::remove_file(&path)?;
+                }
+                std::os::unix::fs::symlink(&fi.symlink_target, &path)?;
+                self.files_has_finished.insert(i);
+                rst.files.push(ReceivedFile { path, size, hash });
+                continue;
+            }
+            if size == 0 {
+                File::create(&path)?;
+            } else if !fi.temp_file && self.is_same_file(&path, &fi) {
+                eprintln!("Skipping {}, already have it", path.display());
+                self.files_has_finished.insert(i);
+                rst.files.push(ReceivedFile { path, size, hash });
+                continue;
+            } else {
+                // pick up where an interrupted transfer of the same file stopped
+                let mut record = ChunkRecord::load(&path, &fi);
+                let missing = record.missing();
+                if record.received() > 0 {
+                    eprintln!(
+                        "Resuming {} from {}",
+                        fi.name,
+                        utils::byte_count_decimal(record.received())
+                    );
+                }
+                let partial = partial_path(&path);
+                if !missing.is_empty() {
+                    let request = RemoteFileRequest {
+                        files_to_transfer_current_num: i,
+                        current_file_chunk_ranges: missing.clone(),
+                    };
+                    message::send(&mut conn, &self.key, &Message::RecipientReady(request))?;
+                    let conns = if data_conns.is_empty() {
+                        std::slice::from_mut(&mut conn)
+                    } else {
+                        &mut data_conns[..]
+                    };
+                    self.receive_file(conns, i, &partial, &missing, &mut record)?;
+                }
+
+                // the chunks may come from several attempts, check all of them together
+                if hash::hash_file(&partial, self.options.hash_algorithm)? != hash {
+                    record.remove();
+                    fs::remove_file(&partial)?;
+                    anyhow::bail!("hashes are not equal for {}, try again", path.display())
+                }
+                record.remove();
+                if fi.temp_file {
+                    // a zipped folder, put the folder in its place straight from the partial
+                    // file, whatever is already called like the zip is left alone
+                    let dest = path.parent().unwrap_or(Path::new("."));
+                    let extracted = archive::unzip(&partial, dest);
+                    fs::remove_file(&partial)?;
+                    let extracted = extracted?;
+                    debug!("extracted {:?} from {}", extracted, fi.name);
+                    self.files_has_finished.insert(i);
+                    let path = extracted.into_iter().next().unwrap_or(path);
+                    rst.files.push(ReceivedFile { path, size, hash });
+                    continue;
+                }
+                fs::rename(&partial, &path)?;
+            }
+            set_file_attributes(&path, &fi)?;
+            self.files_has_finished.insert(i);
+            rst.files.push(ReceivedFile { path, size, hash });
+        }
+
+        message::send(&mut conn, &self.key, &Message::Finished)?;
+        // the sender confirms, but we already have everything
+        if let Err(e) = message::receive(&mut conn, &self.key) {
+            debug!("sender did not confirm: {:?}", e);
+        }
+        Ok(rst)
+    }
+
+    // receive the requested ranges into the partial file,
+    // the sender spreads them over all the given connections
+    fn receive_file(
+        &self,
+        conns: &mut [comm::Comm],
+        num: usize,
+        path: &Path,
+        ranges: &[(u64, u64)],
+        record: &mut ChunkRecord,
+    ) -> anyhow::Result<()> {
+        let fi = &self.files_to_transfer[num];
+        let file = OpenOptions::new().write(true).create(true).truncate(false).open(path)?;
+        file.set_len(fi.size)?;
+        let mut progress = Progress::new(&fi.name, fi.size);
+        progress.resume(record.received());
+        let progress = Mutex::new(progress);
+        let record = Mutex::new(record);
+
+        let parts = split_ranges(ranges, conns.len());
+        let key = &self.key;
+        let rst = std::thread::scope(|s| {
+            let handles: Vec<_> = conns
+                .iter_mut()
+                .zip(parts)
+                .map(|(conn, ranges)| {
+                    let expected: u64 = ranges.iter().map(|(s, e)| e - s).sum();
+                    let (file, progress, record) = (&file, &progress, &record);
+                    s.spawn(move || receive_ranges(conn, key, fi, file, expected, progress, record))
+                })
+                .collect();
+            let mut rst = Ok(());
+            for h in handles {
+                let r = h.join().map_err(|_| anyhow::anyhow!("receiving thread panicked"));
+                if let Err(e) = r.and_then(|x| x) {
+                    rst = Err(e);
+                }
+            }
+            rst
+        });
+        // keep what arrived, even when the transfer broke off
+        record.lock().save()?;
+        rst?;
+        progress.lock().finish();
+        Ok(())
+    }
+
+    // receive a file in order into writer: a stream up to its end marker, or the blocks
+    // of a file one connection after the other, in the turns the sender dealt them out.
+    // Every chunk is authenticated by the encryption and must arrive where it is expected.
+    fn receive_in_order(
+        &self,
+        conns: &mut [comm::Comm],
+        num: usize,
+        writer: &mut dyn Write,
+    ) -> anyhow::Result<u64> {
+        let fi = &self.files_to_transfer[num];
+        if fi.stream {
+            let mut progress = Progress::new_stream(&fi.name);
+            let mut position: u64 = 0;
+            loop {
+                match message::receive(&mut conns[0], &self.key)? {
+                    Message::Chunk { position: p, data } if p == position => {
+                        writer.write_all(&data)?;
+                        position += data.len() as u64;
+                        progress.add(data.len() as u64);
+                    },
+                    Message::EndOfStream { size } if size == position => {
+                        progress.finish();
+                        return Ok(size);
+                    },
+                    Message::EndOfStream { size } => {
+                        anyhow::bail!("stream ended after {} of {} bytes", position, size)
+                    },
+                    Message::Error(e) => {
+                        anyhow::bail!("sender error: {}", e)
+                    },
+                    _ => {
+                        anyhow::bail!("unexpected message from sender")
+                    },
+                }
+            }
+        }
+
+        let mut progress = Progress::new(&fi.name, fi.size);
+        let parts = split_ranges(&[(0, fi.size)], conns.len());
+        let rounds = parts.iter().map(Vec::len).max().unwrap_or(0);
+        for round in 0..rounds {
+            for (conn, ranges) in conns.iter_mut().zip(&parts) {
+                let Some(&(start, end)) = ranges.get(round) else {
+                    continue;
+                };
+                let mut position = start;
+                while position < end {
+                    match message::receive(conn, &self.key)? {
+                        Message::Chunk { position: p, data }
+                            if p == position && p + data.len() as u64 <= end =>
+                        {
+                            writer.write_all(&data)?;
+                            position += data.len() as u64;
+                            progress.add(data.len() as u64);
+                        },
+                        Message::Error(e) => {
+                            anyhow::bail!("sender error: {}", e)
+                        },
+                        _ => {
+                            anyhow::bail!("unexpected message from sender")
+                        },
+                    }
+                }
+            }
+        }
+        progress.finish();
+        Ok(fi.size)
+    }
+
+    // an existing file with the same content doesn't need to be transferred again
+    fn is_same_file(
+        &self,
+        path: &Path,
+        fi: &FileInfo,
+    ) -> bool {
+        match fs::symlink_metadata(path) {
+            Ok(stat) if stat.is_file() && stat.len() == fi.size => {},
+            _ => return false,
+        }
+        match hash::hash_file(path, self.options.hash_algorithm) {
+            Ok(x) => x == fi.hash,
+            Err(e) => {
+                debug!("could not hash {}: {:?}", path.display(), e);
+                false
+            },
+        }
+    }
+
+    fn print_files_to_receive(&self) {
+        let total_size: u64 = self.files_to_transfer.iter().map(|x| x.size).sum();
+        let what = match self.files_to_transfer.len() {
+            1 => format!("'{}'", self.files_to_transfer[0].name),
+            n => format!("{} files", n),
+        };
+        let mut tips =
+            format!("\rReceiving {} ({})\n", what, utils::byte_count_decimal(total_size));
+        for fi in &self.files_to_transfer {
+            let name = Path::new(&fi.folder_remote).join(&fi.name);
+            tips += &format!("    {} ({})\n", name.display(), utils::byte_count_decimal(fi.size));
+        }
+        if !self.empty_folders_to_transfer.is_empty() {
+            tips += &format!("    and {} empty folders\n", self.empty_folders_to_transfer.len());
+        }
+        eprint!("{}", tips);
+    }
+}
+
+// the sender broadcasts the port of its local relay, see if there is one around
+fn discover_local_sender() -> Option<String> {
+    for ip_version in [peerdiscovery::IPVersion::V4, peerdiscovery::IPVersion::V6] {
+        let settings = peerdiscovery::Settings {
+            limit: 1,
+            payload: b"ok".to_vec(),
+            delay: Duration::from_millis(20),
+            time_limit: Some(Duration::from_millis(200)),
+            disable_broadcast: true,
+            allow_self: true,
+            ip_version,
+            ..Default::default()
+        };
+        let discoveries = match peerdiscovery::discover(&[settings]) {
+            Err(e) => {
+                debug!("could not discover peers: {:?}", e);
+                continue;
+            },
+            Ok(x) => x,
+        };
+        for d in discoveries {
+            let Some(port) = d.payload.strip_prefix(b"croc") else {
+                continue;
+            };
+            let port = String::from_utf8_lossy(port);
+            let address = utils::address_with_port(&d.address, &port);
+            match tcp::ping_server(&address) {
+                Ok(_) => return Some(address),
+                Err(e) => debug!("could not ping {}: {:?}", address, e),
+            }
+        }
+    }
+    None
+}
+
+// read the chunks of one connection until all that was expected on it arrived
+fn receive_ranges(
+    conn: &mut comm::Comm,
+    key: &[u8],
+    fi: &FileInfo,
+    file: &File,
+    expected: u64,
+    progress: &Mutex<Progress>,
+    record: &Mutex<&mut ChunkRecord>,
+) -> anyhow::Result<()> {
+    let mut received: u64 = 0;
+    while received < expected {
+        match message::receive(conn, key)? {
+            Message::Chunk { position, data } => {
+                let end = match position.checked_add(data.len() as u64) {
+                    Some(end) if end <= fi.size => end,
+                    _ => anyhow::bail!("sender sent data past the end of {}", fi.name),
+                };
+                file.write_all_at(&data, position)?;
+                record.lock().add(position, end)?;
+                received += data.len() as u64;
+                progress.lock().add(data.len() as u64);
+            },
+            Message::Error(e) => {
+                anyhow::bail!("sender error: {}", e)
+            },
+            _ => {
+                anyhow::bail!("unexpected message from sender")
+            },
+        }
+    }
+    Ok(())
+}
+
+// HashingWriter hashes everything written to writer
+struct HashingWriter<W> {
+    writer: W,
+    hasher: hash::Hasher,
+}
+
+impl<W: Write> Write for HashingWriter<W> {
+    fn write(
+        &mut self,
+        buf: &[u8],
+    ) -> std::io::Result<usize> {
+        self.writer.write_all(buf)?;
+        self.hasher.update(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.writer.flush()
+    }
+}
+
+// keep the permissions and modification time the file had on the sender
+fn set_file_attributes(
+    path: &Path,
+    fi: &FileInfo,
+) -> anyhow::Result<()> {
+    if fi.mode != 0 {
+        fs::set_permissions(path, fs::Permissions::from_mode(fi.mode & 0o7777))?;
+    }
+    filetime::set_file_mtime(path, filetime::FileTime::from_unix_time(fi.mod_time, 0))?;
+    Ok(())
+}
+
+// join the remote path onto the current folder, refusing anything that would escape it
+fn local_path(
+    folder_remote: &str,
+    name: &str,
+) -> anyhow::Result<PathBuf> {
+    let path = Path::new(folder_remote).join(name);
+    for component in path.components() {
+        match component {
+            Component::Normal(_) | Component::CurDir => {},
+            _ => anyhow::bail!("refusing to write outside the current folder: {}", path.display()),
+        }
+    }
+    Ok(path)
+}
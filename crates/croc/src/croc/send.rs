@@ -0,0 +1,427 @@
+use std::fs::File;
+use std::io::Read;
+use std::os::unix::fs::FileExt;
+use std::path::Path;
+use std::time::Duration;
+
+use parking_lot::Mutex;
+use spake2::{Ed25519Group, Identity, Password, Spake2};
+use tokio::task::JoinSet;
+use tracing::{debug, error};
+
+use super::message::{self, Message};
+use super::{
+    banner_ports, broadcast_on_local_network, connect_to_ports, connect_to_relay, split_ranges,
+    Client, FileInfo, RemoteFileRequest, SenderInfo, PAKE_ID_RECIPIENT, PAKE_ID_SENDER,
+};
+use crate::progress::Progress;
+use crate::throttle::Throttle;
+use crate::{comm, crypt, hash, model, tcp, utils};
+
+impl Client {
+    pub fn send(
+        &mut self,
+        files_info: Vec<FileInfo>,
+        empty_folders_to_transfer: Vec<FileInfo>,
+        total_number_folders: usize,
+    ) -> anyhow::Result<()> {
+        let rst = self.send_files(files_info, empty_folders_to_transfer, total_number_folders);
+        // the zips of folders are only made for this transfer
+        for fi in self.files_to_transfer.iter().filter(|x| x.temp_file) {
+            let fpath = Path::new(&fi.folder_source).join(&fi.name);
+            if let Err(e) = std::fs::remove_file(&fpath) {
+                debug!("could not remove {}: {:?}", fpath.display(), e);
+            }
+            let _ = std::fs::remove_dir(&fi.folder_source);
+        }
+        rst
+    }
+
+    fn send_files(
+        &mut self,
+        files_info: Vec<FileInfo>,
+        empty_folders_to_transfer: Vec<FileInfo>,
+        total_number_folders: usize,
+    ) -> anyhow::Result<()> {
+        self.files_to_transfer = files_info;
+        self.empty_folders_to_transfer = empty_folders_to_transfer;
+        self.total_number_folders = total_number_folders;
+
+        self.send_collect_files()?;
+        let mut flags = String::new();
+        if self.options.relay_address != model::DEFAULT_RELAY && !self.options.only_local {
+            flags += "--relay ";
+            flags += &self.options.relay_address;
+            flags += " ";
+        }
+        if self.options.relay_password != model::DEFAULT_PASSPHRASE {
+            flags += "--pass ";
+            flags += &self.options.relay_password;
+            flags += " ";
+        }
+        let tips = format!(
+            r##"Code is: {}
+
+On the other computer run:
+(For Windows)
+    croc {}{}
+(For Linux/OSX)
+    CROC_SECRET={:?} croc {}
+"##,
+            self.options.shared_secret,
+            flags,
+            self.options.shared_secret,
+            self.options.shared_secret,
+            flags,
+        );
+        std::io::copy(&mut tips.as_bytes(), &mut std::io::stderr())?;
+        // xxxxxxxxxxxxxxxxxxxxxxxxx
+        // if c.Options.Ask {
+        //     machid, _ := machineid.ID()
+        //     fmt.Fprintf(os.Stderr, "\rYour machine ID is '%s'\n", machid)
+        // }
+        // xxxxxxxxxxxxxxxxxxxxxxxxx
+
+        // whichever relay the recipient shows up on first wins
+        let (conn_tx, conn_rx) = crossbeam_channel::unbounded::<anyhow::Result<Recipient>>();
+        let mut waiting = 0;
+        if !self.options.disable_local {
+            self.setup_local_relay()?;
+
+            // broadcast on ipv4
+            let only_local = self.options.only_local;
+            let first_reply_port = self.options.relay_ports[0].clone();
+            std::thread::spawn(move || {
+                broadcast_on_local_network(only_local, &first_reply_port, false);
+            });
+
+            // broadcast on ipv6
+            let first_reply_port = self.options.relay_ports[0].clone();
+            std::thread::spawn(move || {
+                broadcast_on_local_network(only_local, &first_reply_port, true);
+            });
+
+            let address = format!("127.0.0.1:{}", self.options.relay_ports[0]);
+            let password = self.options.relay_password.clone();
+            let room = self.room_name.clone();
+            let tx = conn_tx.clone();
+            std::thread::spawn(move || {
+                // give the local relay some time to start listening
+                std::thread::sleep(Duration::from_millis(500));
+                let rst =
+                    tcp::connect_to_tcp_server(&address, &password, &room, Duration::from_secs(5))
+                        .and_then(|(conn, banner, _)| wait_for_recipient(conn, address, banner));
+                let _ = tx.send(rst);
+            });
+            waiting += 1;
+        }
+
+        if !self.options.only_local {
+            let addresses =
+                [self.options.relay_address6.clone(), self.options.relay_address.clone()];
+            let password = self.options.relay_password.clone();
+            let room = self.room_name.clone();
+            let tx = conn_tx.clone();
+            std::thread::spawn(move || {
+                let rst = connect_to_relay(&addresses, &password, &room).and_then(
+                    |(conn, address, banner, ipaddr)| {
+                        debug!(banner, ipaddr, "connected to relay");
+                        wait_for_recipient(conn, address, banner)
+                    },
+                );
+                let _ = tx.send(rst);
+            });
+            waiting += 1;
+        }
+        drop(conn_tx);
+
+        let mut last_err = anyhow::anyhow!("no relay to connect to");
+        for _ in 0..waiting {
+            match conn_rx.recv()? {
+                Ok(recipient) => {
+                    return self.transfer(recipient);
+                },
+                Err(e) => {
+                    debug!(error = ?e);
+                    last_err = e;
+                },
+            }
+        }
+
+        Err(last_err)
+    }
+
+    fn send_collect_files(&mut self) -> anyhow::Result<()> {
+        let mut total_files_size: u64 = 0;
+        for fi in self.files_to_transfer.iter_mut() {
+            if !fi.symlink_target.is_empty() || fi.stream {
+                continue;
+            }
+            let fpath = Path::new(&fi.folder_source).join(&fi.name);
+            fi.hash = hash::hash_file(&fpath, self.options.hash_algorithm)?;
+            total_files_size += fi.size;
+            debug!("file {} has hash {:x?}", fpath.display(), fi.hash);
+        }
+
+        let fname = match (&self.options.text, self.files_to_transfer.len()) {
+            (Some(text), _) => {
+                total_files_size = text.len() as u64;
+                "text".into()
+            },
+            (None, 1) if self.files_to_transfer[0].stream => {
+                eprintln!("Sending stdin");
+                return Ok(());
+            },
+            (None, 1) => format!("'{}'", self.files_to_transfer[0].name),
+            (None, n) => format!("{} files", n),
+        };
+        eprintln!("Sending {} ({})", fname, utils::byte_count_decimal(total_files_size));
+        Ok(())
+    }
+
+    fn setup_local_relay(&mut self) -> anyhow::Result<()> {
+        // setup the relay locally
+        let first_port: u16 = self.options.relay_ports[0].parse()?;
+        let open_ports =
+            utils::find_open_ports("127.0.0.1", first_port, self.options.relay_ports.len());
+        if open_ports.len() < self.options.relay_ports.len() {
+            anyhow::bail!("not enough open ports to run local relay")
+        }
+        self.options.relay_ports.clear();
+        for port in open_ports {
+            self.options.relay_ports.push(port.to_string());
+        }
+
+        let rt = tokio::runtime::Builder::new_current_thread().enable_all().build()?;
+        let password = self.options.relay_password.clone();
+        let banner: String = self.options.relay_ports[1..].join(",");
+        let ports = self.options.relay_ports.clone();
+        std::thread::spawn(move || {
+            rt.block_on(async move {
+                let mut servers = JoinSet::new();
+                for port in ports {
+                    servers.spawn(tcp::run(
+                        "127.0.0.1",
+                        port,
+                        password.clone(),
+                        banner.clone(),
+                        Default::default(),
+                    ));
+                }
+                while let Some(rst) = servers.join_next().await {
+                    if let Ok(Err(e)) = rst {
+                        error!(error = ?e);
+                    }
+                }
+            });
+        });
+
+        Ok(())
+    }
+
+    fn transfer(
+        &mut self,
+        recipient: Recipient,
+    ) -> anyhow::Result<()> {
+        let mut conn = recipient.conn;
+        // the recipient starts the PAKE, we answer with our part and the salt
+        let Message::Pake { bytes, .. } = recipient.first else {
+            anyhow::bail!("expected the recipient to start with the PAKE")
+        };
+        let (pake, pake_bytes) = Spake2::<Ed25519Group>::start_b(
+            &Password::new(self.options.shared_secret.as_bytes()),
+            &Identity::new(PAKE_ID_RECIPIENT),
+            &Identity::new(PAKE_ID_SENDER),
+        );
+        let session_key = pake.finish(&bytes).map_err(|e| anyhow::anyhow!("{:?}", e))?;
+        let (key, salt) = crypt::new(&session_key, &[])?;
+        message::send(&mut conn, &[], &Message::Pake { bytes: pake_bytes, salt })?;
+        self.key = key;
+        self.step1_channel_secured = true;
+
+        let ports = banner_ports(&recipient.banner);
+        // a stream is read once and in order, it goes over the main connection
+        let no_multiplexing = self.options.no_multi
+            || ports.is_empty()
+            || self.files_to_transfer.iter().any(|x| x.stream);
+        let info = SenderInfo {
+            files_to_transfer: self.files_to_transfer.clone(),
+            empty_folders_to_transfer: self.empty_folders_to_transfer.clone(),
+            total_number_folders: self.total_number_folders,
+            no_multiplexing,
+            hash_algorithm: self.options.hash_algorithm,
+            text: self.options.text.clone(),
+        };
+        message::send(&mut conn, &self.key, &Message::FileInfo(info))?;
+
+        // the recipient joins the same rooms on the other ports before asking for data
+        let mut data_conns = vec![];
+        if !no_multiplexing {
+            data_conns = connect_to_ports(
+                &recipient.address,
+                &ports,
+                &self.options.relay_password,
+                &self.room_name,
+            )?;
+            debug!("sending over {} connections", data_conns.len());
+        }
+
+        loop {
+            match message::receive(&mut conn, &self.key)? {
+                Message::RecipientReady(request) => {
+                    let num = request.files_to_transfer_current_num;
+                    if self.files_to_transfer.get(num).is_some_and(|x| x.stream) {
+                        self.send_stream(&mut conn, num)?;
+                    } else if data_conns.is_empty() {
+                        self.send_file(std::slice::from_mut(&mut conn), &request)?;
+                    } else {
+                        self.send_file(&mut data_conns, &request)?;
+                    }
+                },
+                Message::Finished => {
+                    message::send(&mut conn, &self.key, &Message::Finished)?;
+                    debug!("finished sending");
+                    return Ok(());
+                },
+                Message::Error(e) => {
+                    anyhow::bail!("recipient error: {}", e)
+                },
+                _ => {
+                    anyhow::bail!("unexpected message from recipient")
+                },
+            }
+        }
+    }
+
+    // send stdin until it ends, followed by the end of stream marker
+    fn send_stream(
+        &mut self,
+        conn: &mut comm::Comm,
+        num: usize,
+    ) -> anyhow::Result<()> {
+        if !self.files_has_finished.insert(num) {
+            anyhow::bail!("recipient requested stdin again")
+        }
+        let mut stdin = std::io::stdin().lock();
+        let mut progress = Progress::new_stream(&self.files_to_transfer[num].name);
+        let mut buf = vec![0u8; model::TCP_BUFFER_SIZE / 2];
+        let mut position: u64 = 0;
+        loop {
+            let n = match stdin.read(&mut buf) {
+                Ok(0) => break,
+                Ok(n) => n,
+                Err(e) if e.kind() == std::io::ErrorKind::Interrupted => continue,
+                Err(e) => return Err(e.into()),
+            };
+            let chunk = Message::Chunk {
+                position,
+                data: buf[..n].to_vec(),
+            };
+            if let Some(throttle) = &self.throttle {
+                throttle.take(n as u64);
+            }
+            message::send(conn, &self.key, &chunk)?;
+            position += n as u64;
+            progress.add(n as u64);
+        }
+        message::send(conn, &self.key, &Message::EndOfStream { size: position })?;
+        progress.finish();
+        Ok(())
+    }
+
+    // send the requested ranges of a file, spread over all the given connections
+    fn send_file(
+        &mut self,
+        conns: &mut [comm::Comm],
+        request: &RemoteFileRequest,
+    ) -> anyhow::Result<()> {
+        let num = request.files_to_transfer_current_num;
+        let fi = match self.files_to_transfer.get(num) {
+            None => anyhow::bail!("recipient requested unknown file {}", num),
+            Some(x) => x,
+        };
+        for &(start, end) in &request.current_file_chunk_ranges {
+            if end > fi.size || start > end {
+                anyhow::bail!("recipient requested bad range {}-{} of {}", start, end, fi.name)
+            }
+        }
+        let fpath = Path::new(&fi.folder_source).join(&fi.name);
+        let file = File::open(&fpath)?;
+        let total: u64 = request.current_file_chunk_ranges.iter().map(|(s, e)| e - s).sum();
+        let progress = Mutex::new(Progress::new(&fi.name, total));
+
+        let parts = split_ranges(&request.current_file_chunk_ranges, conns.len());
+        let (key, throttle) = (&self.key, self.throttle.as_ref());
+        std::thread::scope(|s| {
+            let handles: Vec<_> = conns
+                .iter_mut()
+                .zip(parts)
+                .map(|(conn, ranges)| {
+                    let (file, progress) = (&file, &progress);
+                    s.spawn(move || send_ranges(conn, key, file, &ranges, progress, throttle))
+                })
+                .collect();
+            for h in handles {
+                h.join().map_err(|_| anyhow::anyhow!("sending thread panicked"))??;
+            }
+            Ok::<_, anyhow::Error>(())
+        })?;
+        progress.lock().finish();
+
+        self.files_has_finished.insert(num);
+        Ok(())
+    }
+}
+
+// the relay keeps us in the room until the recipient joins and starts talking
+fn wait_for_recipient(
+    mut conn: comm::Comm,
+    address: String,
+    banner: String,
+) -> anyhow::Result<Recipient> {
+    let first = message::receive(&mut conn, &[])?;
+    Ok(Recipient {
+        conn,
+        first,
+        address,
+        banner,
+    })
+}
+
+// Recipient is the relay connection the recipient showed up on, with its first message
+struct Recipient {
+    conn: comm::Comm,
+    first: Message,
+    // address of the relay and its banner, to open the parallel connections
+    address: String,
+    banner: String,
+}
+
+fn send_ranges(
+    conn: &mut comm::Comm,
+    key: &[u8],
+    file: &File,
+    ranges: &[(u64, u64)],
+    progress: &Mutex<Progress>,
+    throttle: Option<&Throttle>,
+) -> anyhow::Result<()> {
+    let mut buf = vec![0u8; model::TCP_BUFFER_SIZE / 2];
+    for &(start, end) in ranges {
+        let mut position = start;
+        while position < end {
+            let n = buf.len().min((end - position) as usize);
+            file.read_exact_at(&mut buf[..n], position)?;
+            let chunk = Message::Chunk {
+                position,
+                data: buf[..n].to_vec(),
+            };
+            if let Some(throttle) = throttle {
+                throttle.take(n as u64);
+            }
+            message::send(conn, key, &chunk)?;
+            position += n as u64;
+            progress.lock().add(n as u64);
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,274 @@
+use std::fs::File;
+use std::io::{Read, Seek, SeekFrom};
+use std::path::Path;
+use std::str::FromStr;
+
+use anyhow::Context;
+use md5::{Digest, Md5};
+use serde::{Deserialize, Serialize};
+use xxhash_rust::xxh64::Xxh64;
+
+// imohash parameters, files below the threshold are hashed completely
+const IMOHASH_SAMPLE_SIZE: u64 = 16 * 1024;
+const IMOHASH_SAMPLE_THRESHOLD: u64 = 128 * 1024;
+
+// Algorithm is the hash used to compare the content of files on both sides
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Algorithm {
+    #[default]
+    Xxhash,
+    // imohash only reads samples of big files, fast but not a full check
+    Imohash,
+    Md5,
+}
+
+impl FromStr for Algorithm {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "xxhash" => Ok(Algorithm::Xxhash),
+            "imohash" => Ok(Algorithm::Imohash),
+            "md5" => Ok(Algorithm::Md5),
+            _ => anyhow::bail!("unsupported hash algorithm: {}", s),
+        }
+    }
+}
+
+// HashFile returns the hash of a file
+pub fn hash_file(
+    fname: &Path,
+    algorithm: Algorithm,
+) -> anyhow::Result<Vec<u8>> {
+    let mut f = File::open(fname).with_context(|| format!("could not open {:?}", fname))?;
+    if algorithm == Algorithm::Imohash {
+        return imohash(&mut f);
+    }
+    let mut h = Hasher::new(algorithm, f.metadata()?.len());
+    let mut buf = vec![0u8; 64 * 1024];
+    loop {
+        let n = f.read(&mut buf)?;
+        if n == 0 {
+            break;
+        }
+        h.update(&buf[..n]);
+    }
+    h.finish()
+}
+
+enum State {
+    Xxhash(Xxh64),
+    // the imohash samples gathered so far
+    Imohash(Vec<u8>),
+    Md5(Md5),
+}
+
+// Hasher hashes content of a known size as it goes by, in order.
+// The hash is the same hash_file gives for a file with that content.
+pub struct Hasher {
+    state: State,
+    size: u64,
+    position: u64,
+}
+
+impl Hasher {
+    pub fn new(
+        algorithm: Algorithm,
+        size: u64,
+    ) -> Hasher {
+        let state = match algorithm {
+            Algorithm::Xxhash => State::Xxhash(Xxh64::new(0)),
+            Algorithm::Imohash => State::Imohash(vec![]),
+            Algorithm::Md5 => State::Md5(Md5::new()),
+        };
+        Hasher { state, size, position: 0 }
+    }
+
+    pub fn update(
+        &mut self,
+        data: &[u8],
+    ) {
+        let start = self.position;
+        self.position += data.len() as u64;
+        match &mut self.state {
+            State::Xxhash(h) => h.update(data),
+            State::Md5(h) => h.update(data),
+            State::Imohash(samples) => {
+                for (from, to) in imohash_samples(self.size) {
+                    let (from, to) = (from.max(start), to.min(self.position));
+                    if from < to {
+                        samples.extend_from_slice(
+                            &data[(from - start) as usize..(to - start) as usize],
+                        );
+                    }
+                }
+            },
+        }
+    }
+
+    pub fn finish(self) -> anyhow::Result<Vec<u8>> {
+        if self.position != self.size {
+            anyhow::bail!("hashed {} of {} bytes", self.position, self.size)
+        }
+        match self.state {
+            State::Xxhash(h) => Ok(h.digest().to_be_bytes().to_vec()),
+            State::Md5(h) => Ok(h.finalize().to_vec()),
+            State::Imohash(samples) => imohash_sum(&samples, self.size),
+        }
+    }
+}
+
+// the ranges imohash reads from content of size bytes
+fn imohash_samples(size: u64) -> Vec<(u64, u64)> {
+    if size < IMOHASH_SAMPLE_THRESHOLD {
+        return vec![(0, size)];
+    }
+    [0, size / 2, size - IMOHASH_SAMPLE_SIZE]
+        .into_iter()
+        .map(|x| (x, x + IMOHASH_SAMPLE_SIZE))
+        .collect()
+}
+
+// imohash hashes the start, middle and end of big files with murmur3,
+// then puts the size of the file in front so files of different sizes never match
+fn imohash(f: &mut File) -> anyhow::Result<Vec<u8>> {
+    let size = f.metadata()?.len();
+    let mut data = vec![];
+    for (from, to) in imohash_samples(size) {
+        let mut sample = vec![0u8; (to - from) as usize];
+        f.seek(SeekFrom::Start(from))?;
+        f.read_exact(&mut sample)?;
+        data.extend_from_slice(&sample);
+    }
+    imohash_sum(&data, size)
+}
+
+fn imohash_sum(
+    data: &[u8],
+    size: u64,
+) -> anyhow::Result<Vec<u8>> {
+    let h = murmur3::murmur3_x64_128(&mut &data[..], 0)?;
+    // same byte order as the reference implementation: h1 then h2, big endian
+    let mut hash = [0u8; 16];
+    hash[..8].copy_from_slice(&(h as u64).to_be_bytes());
+    hash[8..].copy_from_slice(&((h >> 64) as u64).to_be_bytes());
+
+    let mut size_varint = size;
+    let mut i = 0;
+    while size_varint >= 0x80 {
+        hash[i] = size_varint as u8 | 0x80;
+        size_varint >>= 7;
+        i += 1;
+    }
+    hash[i] = size_varint as u8;
+    Ok(hash.to_vec())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use super::*;
+
+    // write puts data in a file of its own for the test
+    fn write(
+        name: &str,
+        data: &[u8],
+    ) -> PathBuf {
+        let path = std::env::temp_dir().join(format!("croc-hash-{}-{}", name, std::process::id()));
+        std::fs::write(&path, data).unwrap();
+        path
+    }
+
+    fn hex(hash: &[u8]) -> String {
+        hash.iter().map(|x| format!("{:02x}", x)).collect()
+    }
+
+    // counting bytes, so every sample of imohash is different
+    fn counting(n: usize) -> Vec<u8> {
+        (0..n).map(|i| i as u8).collect()
+    }
+
+    #[test]
+    fn known_digests() {
+        let path = write("known", b"hello croc");
+        assert_eq!(hex(&hash_file(&path, Algorithm::Xxhash).unwrap()), "049a419366534efb");
+        assert_eq!(
+            hex(&hash_file(&path, Algorithm::Md5).unwrap()),
+            "acb70704672a6218f38b098bf1f491b0"
+        );
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn imohash_layout() {
+        // below the threshold all of it is hashed, above it three samples,
+        // the size goes in front as a varint
+        for (size, want) in [
+            (0, "00000000000000000000000000000000"),
+            (127, "7f9ff0bd66af434b11f52742b6db415e"),
+            (128, "80014d3ba5f17e53aba0a7afb68d802e"),
+            (131071, "ffff075256aa8d3a6f525aa8eea7b94b"),
+            (131072, "808008dbf95a4034e54e115bee9ca159"),
+            (500000, "a0c21e442dedbd70fda386a8817b4ca5"),
+        ] {
+            let path = write(&format!("imo{}", size), &counting(size));
+            assert_eq!(hex(&hash_file(&path, Algorithm::Imohash).unwrap()), want, "{}", size);
+            std::fs::remove_file(path).unwrap();
+        }
+    }
+
+    #[test]
+    fn imohash_ignores_what_is_between_samples() {
+        let mut data = counting(500000);
+        let path = write("between", &data);
+        let before = hash_file(&path, Algorithm::Imohash).unwrap();
+        data[100000] ^= 0xff;
+        std::fs::write(&path, &data).unwrap();
+        assert_eq!(hash_file(&path, Algorithm::Imohash).unwrap(), before);
+        data[0] ^= 0xff;
+        std::fs::write(&path, &data).unwrap();
+        assert_ne!(hash_file(&path, Algorithm::Imohash).unwrap(), before);
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn hasher_matches_hash_file() {
+        for size in [0, 1000, 131071, 500000] {
+            let data = counting(size);
+            let path = write(&format!("hasher{}", size), &data);
+            for algorithm in [Algorithm::Xxhash, Algorithm::Imohash, Algorithm::Md5] {
+                let mut h = Hasher::new(algorithm, size as u64);
+                // uneven chunks, so samples start and end inside of them
+                let mut rest = &data[..];
+                for n in [1, 7, 16383, 16385, 65536].iter().cycle() {
+                    if rest.is_empty() {
+                        break;
+                    }
+                    let (chunk, after) = rest.split_at((*n).min(rest.len()));
+                    h.update(chunk);
+                    rest = after;
+                }
+                let want = hash_file(&path, algorithm).unwrap();
+                assert_eq!(h.finish().unwrap(), want, "{:?} of {} bytes", algorithm, size);
+            }
+            std::fs::remove_file(path).unwrap();
+        }
+    }
+
+    #[test]
+    fn hasher_wants_all_of_the_size() {
+        let mut h = Hasher::new(Algorithm::Xxhash, 10);
+        h.update(b"short");
+        assert_eq!(h.finish().unwrap_err().to_string(), "hashed 5 of 10 bytes");
+    }
+
+    #[test]
+    fn parse_algorithm() {
+        assert_eq!("xxhash".parse::<Algorithm>().unwrap(), Algorithm::Xxhash);
+        assert_eq!("IMOHASH".parse::<Algorithm>().unwrap(), Algorithm::Imohash);
+        assert_eq!("md5".parse::<Algorithm>().unwrap(), Algorithm::Md5);
+        let err = "sha1".parse::<Algorithm>().unwrap_err();
+        assert_eq!(err.to_string(), "unsupported hash algorithm: sha1");
+    }
+}
@@ -4,8 +4,12 @@ mod cli;
 mod comm;
 mod croc;
 mod crypt;
+mod hash;
+mod mnemonic;
 mod model;
+mod progress;
 mod tcp;
+mod throttle;
 mod utils;
 
 fn main() -> anyhow::Result<()> {
@@ -14,9 +18,10 @@ fn main() -> anyhow::Result<()> {
         .with_target(true)
         .with_file(true)
         .with_line_number(true)
+        // stdout may carry the received data
+        .with_writer(std::io::stderr)
         .init();
 
     let cli = cli::App::parse();
-    let _rst = cli.run();
-    Ok(())
+    cli.run()
 }
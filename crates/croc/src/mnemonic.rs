@@ -0,0 +1,35 @@
+// WORDS is the list the code phrases are made of, one word for every value of a byte.
+// The words are short, common and far enough apart to be read out loud.
+pub const WORDS: [&str; 256] = [
+    "acid", "actor", "adobe", "agent", "alarm", "album", "alpha", "amber", "angle", "apple",
+    "april", "arena", "armor", "arrow", "atlas", "audio", "bacon", "badge", "baker", "bamboo",
+    "banjo", "basil", "beach", "berry", "bison", "blade", "blaze", "bloom", "bonus", "brave",
+    "bread", "brick", "bronze", "brush", "cabin", "cable", "camel", "canal", "candy", "canoe",
+    "cargo", "castle", "cedar", "chalk", "chess", "chief", "cider", "circus", "civic", "clerk",
+    "cliff", "clock", "cloud", "cobra", "cocoa", "comet", "coral", "cotton", "crane", "crater",
+    "crown", "cubic", "cycle", "daisy", "dance", "delta", "denim", "desert", "dingo", "dragon",
+    "dream", "drum", "eagle", "earth", "echo", "elbow", "elder", "ember", "engine", "epic",
+    "equal", "ether", "fabric", "falcon", "fancy", "fiber", "field", "finch", "flame", "flute",
+    "focus", "forest", "fossil", "frost", "fruit", "galaxy", "garden", "gecko", "ginger", "globe",
+    "gold", "grape", "gravel", "guitar", "habit", "hammer", "harbor", "hazel", "helium", "herbal",
+    "hero", "honey", "hotel", "humble", "hybrid", "icon", "igloo", "index", "iris", "island",
+    "ivory", "jacket", "jaguar", "jelly", "jersey", "jewel", "judge", "juice", "jungle", "kayak",
+    "kernel", "kettle", "kiwi", "koala", "label", "ladder", "lagoon", "lemon", "level", "lilac",
+    "linen", "lion", "llama", "lobby", "locket", "lotus", "lunar", "magnet", "mango", "maple",
+    "marble", "medal", "melon", "meteor", "metro", "mirror", "mocha", "monkey", "mosaic", "motor",
+    "mural", "museum", "nectar", "needle", "neon", "nickel", "ninja", "noble", "noodle", "novel",
+    "nugget", "oasis", "ocean", "olive", "omega", "onion", "opera", "orbit", "orchid", "otter",
+    "oxygen", "paddle", "palace", "panda", "paper", "parrot", "pastel", "pepper", "piano", "pilot",
+    "pixel", "planet", "plaza", "polar", "poppy", "prism", "pulse", "puzzle", "quartz", "quest",
+    "quiet", "quilt", "rabbit", "radar", "radio", "raven", "ribbon", "rocket", "rodeo", "ruby",
+    "saddle", "salad", "salmon", "satin", "scout", "shadow", "silver", "sketch", "socket", "solar",
+    "sonic", "spider", "spiral", "stereo", "summit", "sunset", "tango", "temple", "tennis",
+    "tiger", "timber", "toast", "tomato", "topaz", "tulip", "tunnel", "turtle", "ultra", "urban",
+    "velvet", "violin", "vivid", "voyage", "wagon", "walnut", "whale", "willow", "window",
+    "winter", "wizard", "yacht", "yogurt", "zebra", "zenith", "zigzag", "zipper",
+];
+
+// Encode turns every byte into the word at its position in the list
+pub fn encode(bytes: &[u8]) -> Vec<&'static str> {
+    bytes.iter().map(|&b| WORDS[b as usize]).collect()
+}
@@ -4,4 +4,8 @@ pub const TCP_BUFFER_SIZE: usize = 1024 * 64;
 // DEFAULT_RELAY is the default relay used (can be set using --relay)
 pub const DEFAULT_RELAY: &str = "croc.schollz.com";
 pub const DEFAULT_RELAY6: &str = "croc6.schollz.com";
+pub const DEFAULT_PORT: &str = "9009";
 pub const DEFAULT_PASSPHRASE: &str = "pass123";
+
+// MIN_CODE_LENGTH is the shortest code phrase accepted, shorter ones are too easy to guess
+pub const MIN_CODE_LENGTH: usize = 6;
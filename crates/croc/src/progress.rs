@@ -0,0 +1,108 @@
+use std::io::Write;
+use std::time::{Duration, Instant};
+
+use crate::utils;
+
+const PRINT_INTERVAL: Duration = Duration::from_millis(100);
+
+// Progress prints how far a transfer has come, on stderr
+pub struct Progress {
+    name: String,
+    total: u64,
+    current: u64,
+    printed: u64,
+    last_print: Option<Instant>,
+    stream: bool,
+    // the rate only counts what was transferred since the start
+    started: Instant,
+    resumed: u64,
+}
+
+impl Progress {
+    pub fn new(
+        name: &str,
+        total: u64,
+    ) -> Progress {
+        Progress {
+            name: name.into(),
+            total,
+            current: 0,
+            printed: 0,
+            last_print: None,
+            stream: false,
+            started: Instant::now(),
+            resumed: 0,
+        }
+    }
+
+    // NewStream is a progress for data of unknown size
+    pub fn new_stream(name: &str) -> Progress {
+        Progress {
+            stream: true,
+            ..Progress::new(name, 0)
+        }
+    }
+
+    pub fn add(
+        &mut self,
+        n: u64,
+    ) {
+        self.current += n;
+        if self.last_print.map_or(true, |x| x.elapsed() >= PRINT_INTERVAL) {
+            self.print();
+        }
+    }
+
+    // Resume counts what an earlier attempt already transferred
+    pub fn resume(
+        &mut self,
+        n: u64,
+    ) {
+        self.current += n;
+        self.resumed += n;
+    }
+
+    pub fn finish(&mut self) {
+        if self.last_print.is_none() || self.printed != self.current {
+            self.print();
+        }
+        eprintln!();
+    }
+
+    fn print(&mut self) {
+        let mut stderr = std::io::stderr();
+        let _ = if self.stream {
+            // a stream has no size, only show how much went through
+            write!(
+                stderr,
+                "\r{} |{}, {}/s|",
+                self.name,
+                utils::byte_count_decimal(self.current),
+                utils::byte_count_decimal(self.rate()),
+            )
+        } else {
+            let percent = (self.current * 100).checked_div(self.total).unwrap_or(100);
+            write!(
+                stderr,
+                "\r{} {:>3}% |{}/{}, {}/s|",
+                self.name,
+                percent,
+                utils::byte_count_decimal(self.current),
+                utils::byte_count_decimal(self.total),
+                utils::byte_count_decimal(self.rate()),
+            )
+        };
+        let _ = stderr.flush();
+        self.printed = self.current;
+        self.last_print = Some(Instant::now());
+    }
+
+    // bytes per second since the start
+    fn rate(&self) -> u64 {
+        let elapsed = self.started.elapsed().as_secs_f64();
+        if elapsed <= 0.0 {
+            return 0;
+        }
+        ((self.current - self.resumed) as f64 / elapsed) as u64
+    }
+}
@@ -0,0 +1,164 @@
+use std::net::IpAddr;
+use std::path::{Path, PathBuf};
+
+use anyhow::Context;
+use ipnet::IpNet;
+use parking_lot::RwLock;
+use tracing::info;
+
+// AccessList decides which peers the relay serves. A denied peer is never served,
+// when there are allow rules a peer has to match one of them.
+#[derive(Debug, Default)]
+pub struct AccessList {
+    allow: Vec<IpNet>,
+    deny: Vec<IpNet>,
+}
+
+impl AccessList {
+    // Parse reads one rule per line, "allow <cidr>" or "deny <cidr>",
+    // a plain address is a single host and # starts a comment
+    pub fn parse(text: &str) -> anyhow::Result<AccessList> {
+        let mut list = AccessList::default();
+        for (i, line) in text.lines().enumerate() {
+            let line = line.split('#').next().unwrap_or_default().trim();
+            if line.is_empty() {
+                continue;
+            }
+            let (action, net) = match line.split_once(char::is_whitespace) {
+                None => {
+                    anyhow::bail!("line {}: expected \"allow <cidr>\" or \"deny <cidr>\"", i + 1)
+                },
+                Some((action, net)) => (action, net.trim()),
+            };
+            let net = match net.parse::<IpNet>() {
+                Ok(x) => x,
+                Err(_) => match net.parse::<IpAddr>() {
+                    Ok(x) => IpNet::from(x),
+                    Err(_) => anyhow::bail!("line {}: bad cidr {:?}", i + 1, net),
+                },
+            };
+            match action {
+                "allow" => list.allow.push(net.trunc()),
+                "deny" => list.deny.push(net.trunc()),
+                _ => anyhow::bail!("line {}: unknown action {:?}", i + 1, action),
+            }
+        }
+        Ok(list)
+    }
+
+    pub fn permits(
+        &self,
+        ip: IpAddr,
+    ) -> bool {
+        if self.deny.iter().any(|x| x.contains(&ip)) {
+            return false;
+        }
+        self.allow.is_empty() || self.allow.iter().any(|x| x.contains(&ip))
+    }
+}
+
+// Access holds the access list of the relay and the file it came from, to reload it in place
+#[derive(Debug, Default)]
+pub struct Access {
+    path: Option<PathBuf>,
+    list: RwLock<AccessList>,
+}
+
+impl Access {
+    // Load reads the access list from path, an error leaves the relay unstarted
+    pub fn load(path: &Path) -> anyhow::Result<Access> {
+        let access = Access {
+            path: Some(path.to_path_buf()),
+            list: Default::default(),
+        };
+        access.reload()?;
+        Ok(access)
+    }
+
+    // Reload reads the file again, on error the old list stays in place.
+    // Only new connections are checked, rooms that are already piping go on.
+    pub fn reload(&self) -> anyhow::Result<()> {
+        let Some(path) = &self.path else {
+            return Ok(());
+        };
+        let text = std::fs::read_to_string(path)
+            .with_context(|| format!("could not read access list {}", path.display()))?;
+        let list = AccessList::parse(&text)
+            .with_context(|| format!("could not parse access list {}", path.display()))?;
+        info!(
+            "loaded access list {}: {} allow, {} deny",
+            path.display(),
+            list.allow.len(),
+            list.deny.len()
+        );
+        *self.list.write() = list;
+        Ok(())
+    }
+
+    pub fn permits(
+        &self,
+        ip: IpAddr,
+    ) -> bool {
+        self.list.read().permits(ip)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ip(s: &str) -> IpAddr {
+        s.parse().unwrap()
+    }
+
+    #[test]
+    fn parse_cidrs_and_bare_addresses() {
+        let list = AccessList::parse(
+            "# office and vpn\n\
+             allow 10.1.2.3/16\n\
+             allow 192.168.7.9   # the nas\n\
+             \n\
+             allow fd00::/8\n\
+             deny 10.1.66.0/24\n",
+        )
+        .unwrap();
+        // host bits are dropped, a bare address is a single host
+        assert_eq!(
+            list.allow,
+            vec![
+                "10.1.0.0/16".parse::<IpNet>().unwrap(),
+                "192.168.7.9/32".parse().unwrap(),
+                "fd00::/8".parse().unwrap(),
+            ]
+        );
+        assert!(list.permits(ip("10.1.200.4")));
+        assert!(list.permits(ip("192.168.7.9")));
+        assert!(!list.permits(ip("192.168.7.10")));
+        assert!(list.permits(ip("fd12::1")));
+        assert!(!list.permits(ip("8.8.8.8")));
+        // deny wins over allow
+        assert!(!list.permits(ip("10.1.66.20")));
+    }
+
+    #[test]
+    fn only_deny_rules_permit_everyone_else() {
+        let list = AccessList::parse("deny 203.0.113.5\ndeny 2001:db8::/32").unwrap();
+        assert!(!list.permits(ip("203.0.113.5")));
+        assert!(!list.permits(ip("2001:db8::1")));
+        assert!(list.permits(ip("203.0.113.6")));
+        assert!(AccessList::parse("").unwrap().permits(ip("198.51.100.1")));
+    }
+
+    #[test]
+    fn parse_errors_name_the_line() {
+        for (text, message) in [
+            ("allow 10.0.0.0/8\nallow", "line 2: expected"),
+            ("deny 10.0.0.0/33", "line 1: bad cidr"),
+            ("allow example.com", "line 1: bad cidr"),
+            ("permit 10.0.0.1", "line 1: unknown action"),
+        ] {
+            let err = AccessList::parse(text).unwrap_err();
+            assert!(err.to_string().starts_with(message), "{:?}: {}", text, err);
+        }
+    }
+}
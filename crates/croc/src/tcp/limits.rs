@@ -0,0 +1,264 @@
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::sync::Arc;
+use std::time::Instant;
+
+use parking_lot::Mutex;
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+
+// Limits keep a single host from exhausting the relay, zero turns a limit off
+#[derive(Debug, Clone, Default)]
+pub struct Limits {
+    // new connections per second from one ip, with bursts of up to connection_burst
+    pub connection_rate: f64,
+    pub connection_burst: u32,
+    // rooms one ip can be in at the same time
+    pub max_rooms_per_ip: usize,
+    // connections the relay holds at the same time, from everyone
+    pub max_connections: usize,
+}
+
+// Canonical turns ipv4-mapped ipv6 addresses back into ipv4, so a host has a single bucket
+pub fn canonical(ip: IpAddr) -> IpAddr {
+    match ip {
+        IpAddr::V6(x) => x.to_ipv4_mapped().map_or(ip, IpAddr::V4),
+        _ => ip,
+    }
+}
+
+// Rejection is why a client was turned away by the limits
+#[derive(Debug, Clone, Copy)]
+pub enum Rejection {
+    RateLimited,
+    ConnectionLimit,
+    RoomLimit,
+}
+
+impl Rejection {
+    pub fn reason(&self) -> &'static str {
+        match self {
+            Rejection::RateLimited => "rate_limited",
+            Rejection::ConnectionLimit => "connection_limit",
+            Rejection::RoomLimit => "room_limit",
+        }
+    }
+}
+
+// Admission is what a client holds while it is connected,
+// its share of the limits is given back when it is dropped
+#[derive(Debug, Default)]
+pub struct Admission {
+    _permit: Option<OwnedSemaphorePermit>,
+    room: Option<RoomSlot>,
+}
+
+#[derive(Debug)]
+struct RoomSlot {
+    limiter: Arc<Limiter>,
+    ip: IpAddr,
+    room_key: String,
+}
+
+impl Drop for RoomSlot {
+    fn drop(&mut self) {
+        let mut lock = self.limiter.rooms.lock();
+        let Some(rooms) = lock.get_mut(&self.ip) else {
+            return;
+        };
+        if let Some(count) = rooms.get_mut(&self.room_key) {
+            *count -= 1;
+            if *count == 0 {
+                rooms.remove(&self.room_key);
+            }
+        }
+        if rooms.is_empty() {
+            lock.remove(&self.ip);
+        }
+    }
+}
+
+#[derive(Debug)]
+struct Bucket {
+    tokens: f64,
+    last: Instant,
+}
+
+// Limiter enforces the limits, it keeps the rates and open rooms of every host
+#[derive(Debug)]
+pub struct Limiter {
+    limits: Limits,
+    connections: Option<Arc<Semaphore>>,
+    buckets: Mutex<HashMap<IpAddr, Bucket>>,
+    // the rooms of every ip, a transfer joins the same room on several ports
+    rooms: Mutex<HashMap<IpAddr, HashMap<String, usize>>>,
+}
+
+impl Default for Limiter {
+    fn default() -> Self {
+        Limiter::new(Limits::default())
+    }
+}
+
+impl Limiter {
+    pub fn new(limits: Limits) -> Limiter {
+        let connections = match limits.max_connections {
+            0 => None,
+            x => Some(Arc::new(Semaphore::new(x))),
+        };
+        Limiter {
+            limits,
+            connections,
+            buckets: Mutex::new(HashMap::new()),
+            rooms: Mutex::new(HashMap::new()),
+        }
+    }
+
+    // Admit is asked before anything is read from a new connection
+    pub fn admit(
+        &self,
+        ip: IpAddr,
+    ) -> Result<Admission, Rejection> {
+        if self.limits.connection_rate > 0.0 && !self.take_token(ip) {
+            return Err(Rejection::RateLimited);
+        }
+        let permit = match &self.connections {
+            None => None,
+            Some(x) => Some(x.clone().try_acquire_owned().map_err(|_| Rejection::ConnectionLimit)?),
+        };
+        Ok(Admission {
+            _permit: permit,
+            room: None,
+        })
+    }
+
+    // JoinRoom counts room_key against the rooms of ip for as long as admission lives
+    pub fn join_room(
+        self: &Arc<Self>,
+        admission: &mut Admission,
+        ip: IpAddr,
+        room_key: &str,
+    ) -> Result<(), Rejection> {
+        let mut lock = self.rooms.lock();
+        let max = self.limits.max_rooms_per_ip;
+        let at_limit = lock
+            .get(&ip)
+            .is_some_and(|x| max > 0 && !x.contains_key(room_key) && x.len() >= max);
+        if at_limit {
+            return Err(Rejection::RoomLimit);
+        }
+        *lock.entry(ip).or_default().entry(room_key.to_string()).or_default() += 1;
+        admission.room = Some(RoomSlot {
+            limiter: self.clone(),
+            ip,
+            room_key: room_key.to_string(),
+        });
+        Ok(())
+    }
+
+    // Prune forgets the buckets that filled up again, their hosts are quiet
+    pub fn prune(&self) {
+        let burst = self.burst();
+        self.buckets.lock().retain(|_, bucket| {
+            let tokens =
+                bucket.tokens + bucket.last.elapsed().as_secs_f64() * self.limits.connection_rate;
+            tokens < burst
+        });
+    }
+
+    fn burst(&self) -> f64 {
+        self.limits.connection_burst.max(1) as f64
+    }
+
+    fn take_token(
+        &self,
+        ip: IpAddr,
+    ) -> bool {
+        let burst = self.burst();
+        let mut lock = self.buckets.lock();
+        let bucket = lock.entry(ip).or_insert(Bucket {
+            tokens: burst,
+            last: Instant::now(),
+        });
+        let now = Instant::now();
+        let refill = now.duration_since(bucket.last).as_secs_f64() * self.limits.connection_rate;
+        bucket.tokens = (bucket.tokens + refill).min(burst);
+        bucket.last = now;
+        if bucket.tokens < 1.0 {
+            return false;
+        }
+        bucket.tokens -= 1.0;
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+
+    fn ip(s: &str) -> IpAddr {
+        s.parse().unwrap()
+    }
+
+    #[test]
+    fn burst_then_refill() {
+        let limiter = Limiter::new(Limits {
+            connection_rate: 5.0,
+            connection_burst: 3,
+            ..Default::default()
+        });
+        let host = ip("192.0.2.1");
+        for _ in 0..3 {
+            assert!(limiter.admit(host).is_ok());
+        }
+        assert!(matches!(limiter.admit(host), Err(Rejection::RateLimited)));
+        // the same host over ipv6 has no bucket of its own, another host does
+        let mapped = canonical(ip("::ffff:192.0.2.1"));
+        assert!(matches!(limiter.admit(mapped), Err(Rejection::RateLimited)));
+        assert!(limiter.admit(ip("192.0.2.2")).is_ok());
+        // a token every 200ms
+        std::thread::sleep(Duration::from_millis(250));
+        assert!(limiter.admit(host).is_ok());
+        assert!(matches!(limiter.admit(host), Err(Rejection::RateLimited)));
+    }
+
+    #[test]
+    fn connections_are_given_back_when_dropped() {
+        let limiter = Limiter::new(Limits {
+            max_connections: 2,
+            ..Default::default()
+        });
+        let first = limiter.admit(ip("192.0.2.1")).unwrap();
+        let _second = limiter.admit(ip("192.0.2.2")).unwrap();
+        assert!(matches!(limiter.admit(ip("192.0.2.3")), Err(Rejection::ConnectionLimit)));
+        drop(first);
+        assert!(limiter.admit(ip("192.0.2.3")).is_ok());
+    }
+
+    #[test]
+    fn rooms_are_given_back_when_dropped() {
+        let limiter = Arc::new(Limiter::new(Limits {
+            max_rooms_per_ip: 1,
+            ..Default::default()
+        }));
+        let host = ip("192.0.2.1");
+        let mut first = limiter.admit(host).unwrap();
+        limiter.join_room(&mut first, host, "room1").unwrap();
+        // the other ports of a transfer join the same room
+        let mut second = limiter.admit(host).unwrap();
+        limiter.join_room(&mut second, host, "room1").unwrap();
+        let mut other = limiter.admit(host).unwrap();
+        assert!(matches!(
+            limiter.join_room(&mut other, host, "room2"),
+            Err(Rejection::RoomLimit)
+        ));
+        let mut elsewhere = limiter.admit(ip("192.0.2.2")).unwrap();
+        assert!(limiter.join_room(&mut elsewhere, ip("192.0.2.2"), "room2").is_ok());
+        drop(first);
+        assert!(limiter.join_room(&mut other, host, "room2").is_err());
+        drop(second);
+        assert!(limiter.join_room(&mut other, host, "room2").is_ok());
+        assert!(!limiter.rooms.lock()[&host].contains_key("room1"));
+    }
+}
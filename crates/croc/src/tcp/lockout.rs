@@ -0,0 +1,230 @@
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::time::{Duration, Instant};
+
+use parking_lot::Mutex;
+use sha2::{Digest, Sha256};
+use subtle::ConstantTimeEq;
+
+const MAX_BACKOFF: Duration = Duration::from_secs(60);
+
+// LockoutPolicy is how hard repeated bad passwords from one ip are punished
+#[derive(Debug, Clone)]
+pub struct LockoutPolicy {
+    // the wait after the first bad password, doubled for every further one, zero turns it off
+    pub backoff: Duration,
+    // bad passwords in a row before the ip is banned, zero never bans
+    pub ban_after: u32,
+    pub ban: Duration,
+}
+
+impl Default for LockoutPolicy {
+    fn default() -> Self {
+        LockoutPolicy {
+            backoff: Duration::from_secs(1),
+            ban_after: 10,
+            ban: Duration::from_secs(15 * 60),
+        }
+    }
+}
+
+// Penalty is what an ip got for a bad password
+#[derive(Debug, Clone, Copy)]
+pub enum Penalty {
+    None,
+    Backoff(Duration),
+    Banned(Duration),
+}
+
+// Locked is why an ip can not try again yet
+#[derive(Debug, Clone, Copy)]
+pub enum Locked {
+    Backoff,
+    Banned,
+}
+
+impl Locked {
+    pub fn reason(&self) -> &'static str {
+        match self {
+            Locked::Backoff => "backoff",
+            Locked::Banned => "banned",
+        }
+    }
+}
+
+#[derive(Debug)]
+struct Strikes {
+    // bad passwords in a row
+    count: u32,
+    last: Instant,
+    until: Instant,
+    banned: bool,
+}
+
+// Lockout remembers the bad passwords of every ip and the penalties they earned
+#[derive(Debug, Default)]
+pub struct Lockout {
+    policy: LockoutPolicy,
+    strikes: Mutex<HashMap<IpAddr, Strikes>>,
+}
+
+impl Lockout {
+    pub fn new(policy: LockoutPolicy) -> Lockout {
+        Lockout {
+            policy,
+            strikes: Mutex::new(HashMap::new()),
+        }
+    }
+
+    // Check is asked before a client from ip gets to try a password
+    pub fn check(
+        &self,
+        ip: IpAddr,
+    ) -> Result<(), Locked> {
+        match self.strikes.lock().get(&ip) {
+            Some(x) if x.until > Instant::now() => {
+                Err(if x.banned { Locked::Banned } else { Locked::Backoff })
+            },
+            _ => Ok(()),
+        }
+    }
+
+    pub fn failed(
+        &self,
+        ip: IpAddr,
+    ) -> Penalty {
+        let now = Instant::now();
+        let mut lock = self.strikes.lock();
+        let strikes = lock.entry(ip).or_insert(Strikes {
+            count: 0,
+            last: now,
+            until: now,
+            banned: false,
+        });
+        strikes.count += 1;
+        strikes.last = now;
+        if self.policy.ban_after > 0 && strikes.count >= self.policy.ban_after {
+            strikes.until = now + self.policy.ban;
+            strikes.banned = true;
+            // the ban is the punishment, afterwards the ip starts over
+            strikes.count = 0;
+            return Penalty::Banned(self.policy.ban);
+        }
+        if self.policy.backoff.is_zero() {
+            return Penalty::None;
+        }
+        let wait = self
+            .policy
+            .backoff
+            .saturating_mul(1 << (strikes.count - 1).min(16))
+            .min(MAX_BACKOFF);
+        strikes.until = now + wait;
+        strikes.banned = false;
+        Penalty::Backoff(wait)
+    }
+
+    pub fn succeeded(
+        &self,
+        ip: IpAddr,
+    ) {
+        self.strikes.lock().remove(&ip);
+    }
+
+    // Prune forgets ips that were quiet for a ban period after their last penalty ran out
+    pub fn prune(&self) {
+        let now = Instant::now();
+        let forget = self.policy.ban.max(MAX_BACKOFF);
+        self.strikes
+            .lock()
+            .retain(|_, x| x.until > now || now.duration_since(x.last) < forget);
+    }
+}
+
+// PasswordMatches compares in constant time, hashing first so the length is not given away either
+pub fn password_matches(
+    given: &[u8],
+    expected: &[u8],
+) -> bool {
+    Sha256::digest(given).ct_eq(&Sha256::digest(expected)).into()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ip(s: &str) -> IpAddr {
+        s.parse().unwrap()
+    }
+
+    #[test]
+    fn backoff_doubles_up_to_the_ban() {
+        let lockout = Lockout::new(LockoutPolicy {
+            backoff: Duration::from_millis(100),
+            ban_after: 4,
+            ban: Duration::from_millis(100),
+        });
+        let host = ip("192.0.2.1");
+        assert!(lockout.check(host).is_ok());
+        for wait in [100, 200, 400] {
+            match lockout.failed(host) {
+                Penalty::Backoff(x) => assert_eq!(x, Duration::from_millis(wait)),
+                x => panic!("expected a backoff of {}ms, got {:?}", wait, x),
+            }
+            assert!(matches!(lockout.check(host), Err(Locked::Backoff)));
+        }
+        assert!(matches!(lockout.failed(host), Penalty::Banned(x) if x == lockout.policy.ban));
+        assert!(matches!(lockout.check(host), Err(Locked::Banned)));
+        assert!(lockout.check(ip("192.0.2.2")).is_ok());
+
+        // after the ban the ip starts over
+        std::thread::sleep(Duration::from_millis(120));
+        assert!(lockout.check(host).is_ok());
+        assert!(
+            matches!(lockout.failed(host), Penalty::Backoff(x) if x == Duration::from_millis(100))
+        );
+    }
+
+    #[test]
+    fn backoff_expires_and_success_forgets() {
+        let lockout = Lockout::new(LockoutPolicy {
+            backoff: Duration::from_millis(100),
+            ban_after: 0,
+            ..Default::default()
+        });
+        let host = ip("192.0.2.1");
+        lockout.failed(host);
+        assert!(matches!(lockout.check(host), Err(Locked::Backoff)));
+        std::thread::sleep(Duration::from_millis(120));
+        assert!(lockout.check(host).is_ok());
+        // the strikes are still counted until a good password
+        assert!(
+            matches!(lockout.failed(host), Penalty::Backoff(x) if x == Duration::from_millis(200))
+        );
+        lockout.succeeded(host);
+        assert!(lockout.check(host).is_ok());
+        assert!(
+            matches!(lockout.failed(host), Penalty::Backoff(x) if x == Duration::from_millis(100))
+        );
+    }
+
+    #[test]
+    fn backoff_is_capped_without_a_ban() {
+        let lockout = Lockout::new(LockoutPolicy {
+            ban_after: 0,
+            ..Default::default()
+        });
+        let host = ip("192.0.2.1");
+        let mut last = Penalty::None;
+        for _ in 0..40 {
+            last = lockout.failed(host);
+        }
+        assert!(matches!(last, Penalty::Backoff(x) if x == MAX_BACKOFF));
+    }
+
+    #[test]
+    fn passwords_match() {
+        assert!(password_matches(b"pass123", b"pass123"));
+        assert!(!password_matches(b"pass12", b"pass123"));
+        assert!(!password_matches(b"", b"pass123"));
+    }
+}
@@ -0,0 +1,247 @@
+use std::collections::BTreeMap;
+use std::fmt::Write as _;
+use std::sync::atomic::{AtomicI64, AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+use parking_lot::Mutex;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+use tracing::{debug, info};
+
+use crate::comm;
+
+// a scrape request is a single line and a few headers
+const MAX_REQUEST_SIZE: usize = 8 * 1024;
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(5);
+
+// Metrics counts what the relay does
+#[derive(Debug, Default)]
+pub struct Metrics {
+    pub rooms_active: AtomicI64,
+    pub rooms_created: AtomicU64,
+    // rooms where the second client arrived
+    pub rooms_paired: AtomicU64,
+    // rooms that were cleaned up before they were done
+    pub rooms_expired: AtomicU64,
+    pub bytes_piped: AtomicU64,
+    pub bad_passwords: AtomicU64,
+    // ips banned for too many bad passwords
+    pub bans: AtomicU64,
+    handshake_failures: Mutex<BTreeMap<&'static str, u64>>,
+    rejections: Mutex<BTreeMap<&'static str, u64>>,
+    tenant_bytes: Mutex<BTreeMap<String, u64>>,
+    tenant_rejections: Mutex<BTreeMap<(String, &'static str), u64>>,
+}
+
+impl Metrics {
+    pub fn handshake_failed(
+        &self,
+        reason: &'static str,
+    ) {
+        *self.handshake_failures.lock().entry(reason).or_default() += 1;
+    }
+
+    pub fn rejected(
+        &self,
+        reason: &'static str,
+    ) {
+        *self.rejections.lock().entry(reason).or_default() += 1;
+    }
+
+    pub fn tenant_piped(
+        &self,
+        tenant: &str,
+        bytes: u64,
+    ) {
+        let mut lock = self.tenant_bytes.lock();
+        match lock.get_mut(tenant) {
+            Some(x) => *x += bytes,
+            None => {
+                lock.insert(tenant.to_string(), bytes);
+            },
+        }
+    }
+
+    pub fn tenant_rejected(
+        &self,
+        tenant: &str,
+        reason: &'static str,
+    ) {
+        *self.tenant_rejections.lock().entry((tenant.to_string(), reason)).or_default() += 1;
+    }
+
+    // Render writes all metrics in the prometheus text format
+    pub fn render(&self) -> String {
+        let mut out = String::new();
+        let mut metric = |name: &str, kind: &str, help: &str, value: String| {
+            let _ = writeln!(out, "# HELP {} {}", name, help);
+            let _ = writeln!(out, "# TYPE {} {}", name, kind);
+            let _ = writeln!(out, "{} {}", name, value);
+        };
+        metric(
+            "croc_relay_rooms_active",
+            "gauge",
+            "Rooms that are open right now.",
+            self.rooms_active.load(Ordering::Relaxed).to_string(),
+        );
+        metric(
+            "croc_relay_rooms_created_total",
+            "counter",
+            "Rooms opened since the relay started.",
+            self.rooms_created.load(Ordering::Relaxed).to_string(),
+        );
+        metric(
+            "croc_relay_rooms_paired_total",
+            "counter",
+            "Rooms where the second client arrived.",
+            self.rooms_paired.load(Ordering::Relaxed).to_string(),
+        );
+        metric(
+            "croc_relay_rooms_expired_total",
+            "counter",
+            "Rooms cleaned up after their ttl.",
+            self.rooms_expired.load(Ordering::Relaxed).to_string(),
+        );
+        metric(
+            "croc_relay_bytes_piped_total",
+            "counter",
+            "Bytes forwarded between the clients of rooms, counted as they go.",
+            self.bytes_piped.load(Ordering::Relaxed).to_string(),
+        );
+        metric(
+            "croc_relay_bad_passwords_total",
+            "counter",
+            "Clients that gave the wrong relay password.",
+            self.bad_passwords.load(Ordering::Relaxed).to_string(),
+        );
+        metric(
+            "croc_relay_bans_total",
+            "counter",
+            "Addresses banned for too many bad passwords.",
+            self.bans.load(Ordering::Relaxed).to_string(),
+        );
+
+        for (name, help, values) in [
+            (
+                "croc_relay_handshake_failures_total",
+                "Handshakes that failed, by reason.",
+                &self.handshake_failures,
+            ),
+            (
+                "croc_relay_rejections_total",
+                "Clients turned away by the relay, by reason.",
+                &self.rejections,
+            ),
+        ] {
+            let _ = writeln!(out, "# HELP {} {}", name, help);
+            let _ = writeln!(out, "# TYPE {} counter", name);
+            for (reason, count) in values.lock().iter() {
+                let _ = writeln!(out, "{}{{reason=\"{}\"}} {}", name, reason, count);
+            }
+        }
+
+        let name = "croc_relay_tenant_bytes_piped_total";
+        let _ = writeln!(
+            out,
+            "# HELP {} Bytes forwarded for the rooms of each tenant, counted as they go.",
+            name
+        );
+        let _ = writeln!(out, "# TYPE {} counter", name);
+        for (tenant, bytes) in self.tenant_bytes.lock().iter() {
+            let _ = writeln!(out, "{}{{tenant=\"{}\"}} {}", name, escape(tenant), bytes);
+        }
+        let name = "croc_relay_tenant_rejections_total";
+        let _ = writeln!(out, "# HELP {} Clients of each tenant turned away, by reason.", name);
+        let _ = writeln!(out, "# TYPE {} counter", name);
+        for ((tenant, reason), count) in self.tenant_rejections.lock().iter() {
+            let _ = writeln!(
+                out,
+                "{}{{tenant=\"{}\",reason=\"{}\"}} {}",
+                name,
+                escape(tenant),
+                reason,
+                count
+            );
+        }
+        out
+    }
+}
+
+// label values come from the credentials file
+fn escape(value: &str) -> String {
+    value.replace('\\', "\\\\").replace('"', "\\\"").replace('\n', "\\n")
+}
+
+// HandshakeFailureReason sorts errors of the relay handshake into a few labels
+pub fn handshake_failure_reason(e: &anyhow::Error) -> &'static str {
+    match e.downcast_ref::<comm::CommError>() {
+        Some(comm::CommError::PeerClosed) => "closed",
+        Some(comm::CommError::Timeout) => "timeout",
+        Some(comm::CommError::BadMagic(_)) => "bad_magic",
+        Some(comm::CommError::Oversized { .. }) => "oversized",
+        Some(comm::CommError::Io(_)) => "io",
+        // the key exchange, decryption or the room name went wrong
+        None => "protocol",
+    }
+}
+
+// Serve answers GET /metrics on address until the listener fails
+pub async fn serve(
+    address: String,
+    metrics: Arc<Metrics>,
+) -> anyhow::Result<()> {
+    let listener = match TcpListener::bind(&address).await {
+        Err(e) => {
+            anyhow::bail!("error listening for metrics on {}: {:?}", address, e)
+        },
+        Ok(x) => x,
+    };
+    info!("serving metrics on http://{}/metrics", address);
+    loop {
+        let (stream, addr) = listener.accept().await?;
+        let metrics = metrics.clone();
+        tokio::spawn(async move {
+            match tokio::time::timeout(REQUEST_TIMEOUT, respond(stream, &metrics)).await {
+                Err(_) => debug!("metrics-{}: timed out", addr),
+                Ok(Err(e)) => debug!("metrics-{}: {:?}", addr, e),
+                Ok(Ok(())) => {},
+            }
+        });
+    }
+}
+
+async fn respond(
+    mut stream: TcpStream,
+    metrics: &Metrics,
+) -> anyhow::Result<()> {
+    let mut request = Vec::with_capacity(512);
+    let mut buf = [0u8; 512];
+    while !request.windows(4).any(|x| x == b"\r\n\r\n") {
+        if request.len() > MAX_REQUEST_SIZE {
+            anyhow::bail!("request is too large")
+        }
+        let n = stream.read(&mut buf).await?;
+        if n == 0 {
+            anyhow::bail!("closed before the request was complete")
+        }
+        request.extend_from_slice(&buf[..n]);
+    }
+
+    let request = String::from_utf8_lossy(&request);
+    let mut parts = request.split_whitespace();
+    let (status, body) = match (parts.next(), parts.next()) {
+        (Some("GET"), Some("/metrics")) => ("200 OK", metrics.render()),
+        (Some("GET"), _) => ("404 Not Found", "not found\n".to_string()),
+        _ => ("405 Method Not Allowed", "method not allowed\n".to_string()),
+    };
+    let response = format!(
+        "HTTP/1.1 {}\r\nContent-Type: text/plain; version=0.0.4\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        status,
+        body.len(),
+        body
+    );
+    stream.write_all(response.as_bytes()).await?;
+    stream.shutdown().await?;
+    Ok(())
+}
@@ -1,31 +1,71 @@
-use std::collections::HashMap;
-use std::io::Read;
-use std::net::{SocketAddr, TcpListener, TcpStream, ToSocketAddrs};
+use std::net::{IpAddr, SocketAddr, ToSocketAddrs};
+use std::sync::atomic::{AtomicU64, Ordering};
 use std::sync::Arc;
 use std::time::{Duration, Instant};
 
-use parking_lot::RwLock;
-use socket2::Socket;
 use spake2::{Ed25519Group, Identity, Password, Spake2};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::oneshot;
 use tracing::{debug, error, info};
 
-use crate::{comm, crypt, model};
+use crate::{comm, crypt, utils};
+
+mod access;
+mod limits;
+mod lockout;
+pub mod metrics;
+mod pipe;
+mod room;
+mod tenants;
+
+pub use access::Access;
+use limits::Admission;
+pub use limits::{Limiter, Limits};
+use lockout::Penalty;
+pub use lockout::{Lockout, LockoutPolicy};
+pub use metrics::Metrics;
+use room::{roomMap, Member, RoomState, Seat};
+pub use tenants::Tenants;
 
 const DEFAULT_ROOM_TTL: Duration = Duration::from_secs(3 * 3600); // 3 hour
 const DEFAULT_ROOM_CLEANUP_INTERVAL: Duration = Duration::from_secs(600); // 10 min
 
-const PING_ROOM: &str = "pinglkasjdlfjsaldjf";
 const WEAK_KEY: &[u8] = &[1, 2, 3];
+const HANDSHAKE_MAX_FRAME_SIZE: usize = 64 * 1024;
+
+// Options tune how long the relay keeps rooms around and where it counts them.
+// The servers of all ports get a clone, so what is behind the Arcs applies to the relay as a whole.
+#[derive(Debug, Clone)]
+pub struct Options {
+    // rooms that are not piping are closed this long after they were opened
+    pub room_ttl: Duration,
+    pub room_cleanup_interval: Duration,
+    // piping rooms are closed after this long without a byte in either direction
+    pub idle_timeout: Option<Duration>,
+    pub metrics: Arc<Metrics>,
+    // rates, rooms and connections of every host
+    pub limiter: Arc<Limiter>,
+    // who may connect at all, reloaded in place
+    pub access: Arc<Access>,
+    // bad passwords of every ip
+    pub lockout: Arc<Lockout>,
+    // when there are tenants their passwords are used instead of the relay password
+    pub tenants: Arc<Tenants>,
+}
 
-#[allow(non_camel_case_types)]
-type roomMap = Arc<RwLock<HashMap<String, roomInfo>>>;
-
-#[allow(non_camel_case_types)]
-pub struct roomInfo {
-    full: bool,
-    opened: Instant,
-    first: Option<comm::Comm>,
-    second: Option<comm::Comm>,
+impl Default for Options {
+    fn default() -> Self {
+        Options {
+            room_ttl: DEFAULT_ROOM_TTL,
+            room_cleanup_interval: DEFAULT_ROOM_CLEANUP_INTERVAL,
+            idle_timeout: None,
+            metrics: Default::default(),
+            limiter: Default::default(),
+            access: Default::default(),
+            lockout: Default::default(),
+            tenants: Default::default(),
+        }
+    }
 }
 
 #[allow(non_camel_case_types)]
@@ -35,54 +75,58 @@ pub struct server {
     password: String,
     banner: String,
     rooms: roomMap,
-    room_cleanup_interval: Duration,
-    room_ttl: Duration,
+    options: Options,
+}
+
+// Joined is where a client stands once the handshake is done
+enum Joined {
+    Ping,
+    // first in the room, waits for the second
+    First(String, u64, Box<Member>, oneshot::Receiver<Member>),
+    // handed over to the first
+    Second,
+    // told why it can not stay and closed
+    Rejected(&'static str),
 }
 
-// newDefaultServer initializes a new server, with some default configuration options
-fn new_default_server(
+// newServer initializes a new server with the given options
+fn new_server(
     host: &str,
     port: String,
     password: String,
     banner: String,
+    options: Options,
 ) -> server {
     server {
         host: host.into(),
         port,
         password,
         banner,
-        rooms: Arc::new(RwLock::new(HashMap::new())),
-        room_ttl: DEFAULT_ROOM_TTL,
-        room_cleanup_interval: DEFAULT_ROOM_CLEANUP_INTERVAL,
+        rooms: room::new_map(),
+        options,
     }
 }
 
-// Run starts a tcp listener, run async
-pub fn run(
+// Run starts a tcp listener, every connection is a task on the current runtime
+pub async fn run(
     host: &str,
     port: String,
     password: String,
     banner: String,
+    options: Options,
 ) -> anyhow::Result<()> {
-    let s = new_default_server(host, port, password, banner);
-    s.start()
+    let s = Arc::new(new_server(host, port, password, banner, options));
+    s.start().await
 }
 
 impl server {
-    fn start(&self) -> anyhow::Result<()> {
+    async fn start(self: &Arc<Self>) -> anyhow::Result<()> {
         debug!(target: "starting with password", password = self.password);
 
-        let (stop_tx, stop_rx) = crossbeam_channel::bounded::<()>(1);
-        std::thread::spawn({
-            let rooms = self.rooms.clone();
-            let ttl = self.room_ttl;
-            let interval = self.room_cleanup_interval;
-            move || {
-                delete_old_rooms(rooms, stop_rx, interval, ttl);
-            }
-        });
+        let (stop_tx, stop_rx) = oneshot::channel::<()>();
+        tokio::spawn(delete_old_rooms(self.rooms.clone(), self.options.clone(), stop_rx));
 
-        let rst = self.run();
+        let rst = self.run().await;
         if let Err(ref e) = rst {
             error!(error = ?e);
         }
@@ -93,299 +137,404 @@ impl server {
         rst
     }
 
-    fn run(&self) -> anyhow::Result<()> {
+    async fn run(self: &Arc<Self>) -> anyhow::Result<()> {
         let mut addr = format!("{}:{}", self.host, self.port);
         addr.to_socket_addrs()?;
         addr = addr.replacen("127.0.0.1", "0.0.0.0", 1);
         info!("starting TCP server on {}", addr);
-        let listener = match TcpListener::bind(&addr) {
+        let listener = match TcpListener::bind(&addr).await {
             Err(e) => {
                 anyhow::bail!(format!("error listening on {}: {:?}", addr, e))
             },
             Ok(x) => x,
         };
 
-        // spawn a new goroutine whenever a client connects
-        std::thread::scope(|s| {
-            loop {
-                let (stream, addr) = match listener.accept() {
-                    Err(e) => {
-                        anyhow::bail!(format!("problem accepting connection: {:?}", e))
-                    },
-                    Ok(x) => x,
-                };
-                debug!("client {:?} connected", addr);
-                let port = self.port.clone();
-                let password = self.password.clone();
-                let banner = self.banner.clone();
-                let rooms = self.rooms.clone();
-                let socket = Socket::from(stream);
-
-                s.spawn(move || {
-                    let c = comm::new(socket, addr);
-                    let room_key =
-                        match client_communication(&port, &password, &banner, &rooms, c, &addr) {
-                            Err(e) => {
-                                debug!("relay-{}: {:?}", addr, e);
-                                return;
-                            },
-                            Ok(x) => x,
-                        };
-                    debug!(room_key);
-                    if room_key == PING_ROOM {
-                        debug!("got ping");
-                        return;
-                    }
-
-                    loop {
-                        // check connection
-                        debug!(target: "checking connection", room = room_key);
-                        let mut delete_it = false;
-
-                        let mut lock = rooms.write();
-                        let room: &mut roomInfo = match lock.get_mut(&room_key) {
-                            None => {
-                                debug!("room is gone");
-                                return;
-                            },
-                            Some(x) => x,
-                        };
-
-                        if room.first.is_some() && room.second.is_some() {
-                            debug!("rooms ready");
-                            drop(lock);
-                            break;
-                        } else {
-                            if let Some(ref mut comm) = room.first {
-                                if let Err(e) = comm.send(&[1u8]) {
-                                    debug!(?e);
-                                    delete_it = true;
-                                }
-                            }
-                        }
-                        drop(lock);
-                        if delete_it {
-                            debug!(target: "deleting room", room = room_key);
-                            let mut lock = rooms.write();
-                            lock.remove(&room_key);
-                            break;
-                        }
-
-                        std::thread::sleep(Duration::from_secs(1));
-                    }
-                });
+        // spawn a new task whenever a client connects
+        loop {
+            let (stream, addr) = match listener.accept().await {
+                Err(e) => {
+                    anyhow::bail!(format!("problem accepting connection: {:?}", e))
+                },
+                Ok(x) => x,
+            };
+            debug!("client {:?} connected", addr);
+            // peers outside the access list are dropped before anything is read
+            if !self.options.access.permits(limits::canonical(addr.ip())) {
+                self.options.metrics.rejected("denied");
+                info!("relay-{}: rejected, denied", addr);
+                continue;
             }
-
-            Ok::<_, anyhow::Error>(())
-        })
+            let s = self.clone();
+            tokio::spawn(async move { s.handle(stream, addr).await });
+        }
     }
-}
 
-fn client_communication(
-    _port: &str,
-    password: &str,
-    banner: &str,
-    rooms: &roomMap,
-    mut c: comm::Comm,
-    addr: &SocketAddr,
-) -> anyhow::Result<String> {
-    // establish secure password with PAKE for communication with relay
-    let (b, bbytes) =
-        Spake2::<Ed25519Group>::start_symmetric(&Password::new(WEAK_KEY), &Identity::new(b"siec"));
-    let abytes = c.receive()?;
-    if abytes == b"ping" {
-        debug!("sending back pong");
-        return Ok(PING_ROOM.into());
-    }
-    let strong_key = match b.finish(&abytes) {
-        Err(e) => {
-            anyhow::bail!("{:?}", e)
-        },
-        Ok(x) => x,
-    };
-    c.send(&bbytes)?;
-    // receive salt
-    let salt = c.receive()?;
-    let (strong_encryption, _) = crypt::new(&strong_key, &salt)?;
-    debug!("waiting for password");
-    let password_bytes_enc = c.receive()?;
-    let password_bytes = crypt::decrypt(&password_bytes_enc, &strong_encryption)?;
-    let passwd = String::from_utf8(password_bytes)?;
-    if passwd != password {
-        let enc = crypt::encrypt(b"bad password", &strong_encryption)?;
-        if let Err(e) = c.send(&enc) {
-            anyhow::bail!("send error: {:?}", e)
+    async fn handle(
+        &self,
+        stream: TcpStream,
+        addr: SocketAddr,
+    ) {
+        // the limits are checked before any work is done for the client
+        let ip = limits::canonical(addr.ip());
+        let admission = match self.options.limiter.admit(ip) {
+            Err(rejection) => {
+                self.options.metrics.rejected(rejection.reason());
+                info!("relay-{}: rejected, {}", addr, rejection.reason());
+                return;
+            },
+            Ok(x) => x,
+        };
+        if let Err(locked) = self.options.lockout.check(ip) {
+            self.options.metrics.rejected(locked.reason());
+            info!("relay-{}: rejected, {}", addr, locked.reason());
+            return;
+        }
+        let mut c = comm::new_async(stream, addr);
+        // only the handshake is read by the relay, after that bytes are piped
+        c.set_max_frame_size(HANDSHAKE_MAX_FRAME_SIZE);
+        match self.client_communication(c, ip, admission).await {
+            Err(e) => {
+                let reason = metrics::handshake_failure_reason(&e);
+                self.options.metrics.handshake_failed(reason);
+                match reason {
+                    "closed" => debug!("relay-{}: closed during handshake", addr),
+                    _ => debug!("relay-{}: {:?}", addr, e),
+                }
+            },
+            Ok(Joined::Ping) => debug!("got ping"),
+            Ok(Joined::Second) => debug!("relay-{}: joined as second", addr),
+            Ok(Joined::Rejected(reason)) => {
+                self.options.metrics.rejected(reason);
+                info!("relay-{}: rejected, {}", addr, reason);
+            },
+            Ok(Joined::First(room_key, id, first, second_rx)) => {
+                debug!(room_key);
+                self.wait_for_second(&room_key, id, *first, second_rx).await;
+            },
         }
-        anyhow::bail!("bad password")
     }
 
-    // send ok to tell client they are connected
-    let baner = if banner.is_empty() { "ok" } else { banner };
-    debug!(target: "sending", banner = ?baner);
-    let msg = format!("{}|||{}", baner, addr);
-    let bsend = crypt::encrypt(msg.as_bytes(), &strong_encryption)?;
-    c.send(&bsend)?;
-    // wait for client to tell me which room they want
-    debug!("waiting for answer");
-    let enc = c.receive()?;
-    let room_bytes = crypt::decrypt(&enc, &strong_encryption)?;
-    let room_key = String::from_utf8(room_bytes)?;
-
-    // create the room if it is new
-    let mut lock = rooms.write();
-    match lock.get_mut(&room_key) {
-        None => {
-            let bsend = crypt::encrypt(b"ok", &strong_encryption)?;
-            c.send(&bsend)?;
-            lock.insert(
-                room_key.clone(),
-                roomInfo {
-                    first: Some(c),
-                    second: None,
-                    full: false,
-                    opened: Instant::now(),
+    async fn client_communication(
+        &self,
+        mut c: comm::AsyncComm,
+        ip: IpAddr,
+        mut admission: Admission,
+    ) -> anyhow::Result<Joined> {
+        // establish secure password with PAKE for communication with relay
+        let (b, bbytes) = Spake2::<Ed25519Group>::start_symmetric(
+            &Password::new(WEAK_KEY),
+            &Identity::new(b"siec"),
+        );
+        let abytes = c.receive().await?;
+        if abytes == b"ping" {
+            debug!("sending back pong");
+            c.send(b"pong").await?;
+            return Ok(Joined::Ping);
+        }
+        let strong_key = match b.finish(&abytes) {
+            Err(e) => {
+                anyhow::bail!("{:?}", e)
+            },
+            Ok(x) => x,
+        };
+        c.send(&bbytes).await?;
+        // receive salt
+        let salt = c.receive().await?;
+        let (strong_encryption, _) = crypt::new(&strong_key, &salt)?;
+        debug!("waiting for password");
+        let password_bytes_enc = c.receive().await?;
+        let password_bytes = crypt::decrypt(&password_bytes_enc, &strong_encryption)?;
+        let tenant = if self.options.tenants.is_empty() {
+            lockout::password_matches(&password_bytes, self.password.as_bytes()).then_some(None)
+        } else {
+            self.options.tenants.find(&password_bytes).map(Some)
+        };
+        let Some(tenant) = tenant else {
+            self.options.metrics.bad_passwords.fetch_add(1, Ordering::Relaxed);
+            match self.options.lockout.failed(ip) {
+                Penalty::None => info!("relay-{}: bad password", c.addr()),
+                Penalty::Backoff(wait) => {
+                    info!("relay-{}: bad password, next try in {:?}", c.addr(), wait)
                 },
-            );
-            debug!("room {} has 1", room_key);
-            Ok(room_key)
-        },
-        Some(room) => {
-            if room.full {
+                Penalty::Banned(ban) => {
+                    self.options.metrics.bans.fetch_add(1, Ordering::Relaxed);
+                    info!("relay-{}: too many bad passwords, banned for {:?}", c.addr(), ban)
+                },
+            }
+            let enc = crypt::encrypt(b"bad password", &strong_encryption)?;
+            if let Err(e) = c.send(&enc).await {
+                anyhow::bail!("send error: {:?}", e)
+            }
+            c.close().await;
+            return Ok(Joined::Rejected("bad_password"));
+        };
+        self.options.lockout.succeeded(ip);
+
+        // send ok to tell client they are connected
+        let baner = if self.banner.is_empty() { "ok" } else { self.banner.as_str() };
+        debug!(target: "sending", banner = ?baner);
+        let msg = format!("{}|||{}", baner, c.addr());
+        let bsend = crypt::encrypt(msg.as_bytes(), &strong_encryption)?;
+        c.send(&bsend).await?;
+        // wait for client to tell me which room they want
+        debug!("waiting for answer");
+        let enc = c.receive().await?;
+        let room_bytes = crypt::decrypt(&enc, &strong_encryption)?;
+        let room_key = String::from_utf8(room_bytes)?;
+
+        if let Err(rejection) = self.options.limiter.join_room(&mut admission, ip, &room_key) {
+            let bsend = crypt::encrypt(b"too many rooms from this address", &strong_encryption)?;
+            c.send(&bsend).await?;
+            c.close().await;
+            return Ok(Joined::Rejected(rejection.reason()));
+        }
+        let tenant = match tenant {
+            None => None,
+            Some(tenant) => match tenant.join(&room_key) {
+                Ok(x) => Some(x),
+                Err(limit) => {
+                    self.options.metrics.tenant_rejected(&tenant.name, limit.reason());
+                    let bsend = crypt::encrypt(limit.message().as_bytes(), &strong_encryption)?;
+                    c.send(&bsend).await?;
+                    c.close().await;
+                    return Ok(Joined::Rejected(limit.reason()));
+                },
+            },
+        };
+
+        // the seat is decided under the lock, the client is told after it is released
+        let (rooms, metrics) = (&self.rooms, &self.options.metrics);
+        match room::take_seat(rooms, metrics, &room_key) {
+            Seat::First(id, rx) => {
+                let bsend = crypt::encrypt(b"ok", &strong_encryption)?;
+                if let Err(e) = c.send(&bsend).await {
+                    room::set_state(rooms, metrics, &room_key, id, RoomState::Closed);
+                    return Err(e);
+                }
+                Ok(Joined::First(
+                    room_key,
+                    id,
+                    Box::new(Member {
+                        comm: c,
+                        _admission: admission,
+                        tenant,
+                    }),
+                    rx,
+                ))
+            },
+            Seat::Full => {
                 let bsend = crypt::encrypt(b"room full", &strong_encryption)?;
-                if let Err(e) = c.send(&bsend) {
+                if let Err(e) = c.send(&bsend).await {
                     error!(target: "comm_send", error = ?e);
                     return Err(e);
                 }
+                c.close().await;
+                Ok(Joined::Rejected("room_full"))
+            },
+            Seat::Second(id, tx) => {
+                // second connection is the sender, time to staple connections
+                // tell the sender everything is ready
+                let bsend = crypt::encrypt(b"ok", &strong_encryption)?;
+                if let Err(e) = c.send(&bsend).await {
+                    // dropping tx tells the first
+                    room::set_state(rooms, metrics, &room_key, id, RoomState::Closed);
+                    return Err(e);
+                }
+                if tx
+                    .send(Member {
+                        comm: c,
+                        _admission: admission,
+                        tenant,
+                    })
+                    .is_err()
+                {
+                    room::set_state(rooms, metrics, &room_key, id, RoomState::Closed);
+                    anyhow::bail!("room {} closed before the second joined", room_key)
+                }
+                Ok(Joined::Second)
+            },
+        }
+    }
 
-                return Ok(room_key);
+    // wait_for_second keeps the first connection of a room alive until the second arrives,
+    // then pipes the two together
+    async fn wait_for_second(
+        &self,
+        room_key: &str,
+        id: u64,
+        mut first: Member,
+        mut second_rx: oneshot::Receiver<Member>,
+    ) {
+        let (rooms, metrics) = (&self.rooms, &self.options.metrics);
+        let mut keepalive = tokio::time::interval(Duration::from_secs(1));
+        let second = loop {
+            tokio::select! {
+                second = &mut second_rx => match second {
+                    Ok(x) => break x,
+                    Err(_) => {
+                        debug!("room is gone");
+                        room::set_state(rooms, metrics, room_key, id, RoomState::Closed);
+                        return;
+                    },
+                },
+                _ = keepalive.tick() => {
+                    // check connection
+                    debug!(target: "checking connection", room = room_key);
+                    if let Err(e) = first.comm.send(&[1u8]).await {
+                        debug!(?e);
+                        debug!(target: "closing room", room = room_key);
+                        room::set_state(rooms, metrics, room_key, id, RoomState::Closed);
+                        return;
+                    }
+                },
             }
+        };
 
-            debug!("room {} has 2", room_key);
-            room.full = true;
-
-            let first: &mut comm::Comm = room
-                .first
-                .as_mut()
-                .ok_or(anyhow::anyhow!("room: {} first should not be nil", room_key))?;
-            // second connection is the sender, time to staple connections
-            // tell the sender everything is ready
-            let bsend = crypt::encrypt(b"ok", &strong_encryption)?;
-            if let Err(_e) = c.send(&bsend) {
-                lock.remove(&room_key);
-                return Ok(room_key);
-            }
+        debug!("rooms ready");
+        room::set_state(rooms, metrics, room_key, id, RoomState::Piping);
+        debug!("starting pipes");
+        let slots = first.tenant.iter().chain(second.tenant.iter()).collect();
+        let meter = tenants::Meter::new(slots, metrics);
+        let (first, second) = (first.comm.into_stream(), second.comm.into_stream());
+        let piped = AtomicU64::new(0);
+        let tally = |n| {
+            piped.fetch_add(n, Ordering::Relaxed);
+            metrics.bytes_piped.fetch_add(n, Ordering::Relaxed);
+            meter.charge(n)
+        };
+        let piping = pipe::pipe(&first, &second, &tally);
+        let rst = match self.options.idle_timeout {
+            None => piping.await,
+            Some(timeout) => tokio::select! {
+                rst = piping => rst,
+                _ = idle(&piped, timeout) => {
+                    info!("room {} was idle for {:?}, closing it", room_key, timeout);
+                    Ok(())
+                },
+            },
+        };
+        if let Some((tenant, limit)) = meter.over() {
+            metrics.tenant_rejected(tenant, limit.reason());
+            info!("room {} of tenant {} closed, {}", room_key, tenant, limit.message());
+        }
+        if let Err(e) = rst {
+            // clients hang up without reading the last keepalives, that is how rooms end
+            debug!(?e);
+        }
+        info!(
+            "room {} piped {}, {} in total",
+            room_key,
+            utils::byte_count_decimal(piped.into_inner()),
+            utils::byte_count_decimal(metrics.bytes_piped.load(Ordering::Relaxed))
+        );
+        debug!("done piping");
+        room::set_state(rooms, metrics, room_key, id, RoomState::Closed);
+    }
+}
 
-            // start piping
-            debug!("starting pipes");
-            let _ = pipe(first.connection(), c.connection())?;
-            debug!("done piping");
-            room.second = Some(c);
-            lock.remove(&room_key);
-            Ok(room_key)
-        },
+// idle resolves once counter did not move for timeout
+async fn idle(
+    counter: &AtomicU64,
+    timeout: Duration,
+) {
+    let tick = (timeout / 10).max(Duration::from_millis(100));
+    let mut last = counter.load(Ordering::Relaxed);
+    let mut since = Instant::now();
+    loop {
+        tokio::time::sleep(tick).await;
+        let now = counter.load(Ordering::Relaxed);
+        if now != last {
+            last = now;
+            since = Instant::now();
+        } else if since.elapsed() >= timeout {
+            return;
+        }
     }
 }
 
-fn pipe(
-    a: &mut Socket,
-    b: &mut Socket,
-) -> anyhow::Result<()> {
-    let (txa, rxa) = crossbeam_channel::bounded::<Vec<u8>>(2);
-    let (txb, rxb) = crossbeam_channel::bounded::<Vec<u8>>(2);
-    let writera = a.try_clone()?;
-    let writerb = b.try_clone()?;
-
-    std::thread::scope(|s| {
-        s.spawn(|| {
-            while let Ok(u8s) = rxa.recv() {
-                if let Err(e) = writerb.send(&u8s) {
-                    error!(target: "write error on channel 2", error = ?e);
-                }
-            }
-        });
-        s.spawn(|| {
-            loop {
-                let mut buf = Vec::with_capacity(model::TCP_BUFFER_SIZE);
-                match a.read(&mut buf) {
-                    Ok(n) => {
-                        buf.drain(n..);
-                        let _ = txa.send(buf.clone());
-                    },
-                    Err(e) => {
-                        debug!(?e);
-                        drop(txa);
-                        break;
-                    },
-                }
-            }
+// PingServer will try to ping the server
+pub fn ping_server(address: &str) -> anyhow::Result<()> {
+    debug!("pinging {}", address);
+    let mut c = comm::new_connection(address, Duration::from_millis(300))?;
+    c.send(b"ping")?;
+    let b = c.receive()?;
+    if b == b"pong" {
+        return Ok(());
+    }
+    anyhow::bail!("no pong")
+}
 
-            debug!("exiting");
-        });
+// ConnectToTCPServer will initiate a new connection
+// to the specified address, room with optional time limit
+pub fn connect_to_tcp_server(
+    address: &str,
+    password: &str,
+    room: &str,
+    timelimit: Duration,
+) -> anyhow::Result<(comm::Comm, String, String)> {
+    let mut c = comm::new_connection(address, timelimit)?;
+    // the relay only sends small messages until the room is set up
+    c.set_max_frame_size(HANDSHAKE_MAX_FRAME_SIZE);
+
+    // get PAKE connection with server to establish strong key to transfer info
+    let (a, abytes) =
+        Spake2::<Ed25519Group>::start_symmetric(&Password::new(WEAK_KEY), &Identity::new(b"siec"));
+    c.send(&abytes)?;
+    let bbytes = c.receive()?;
+    let strong_key = match a.finish(&bbytes) {
+        Err(e) => {
+            anyhow::bail!("{:?}", e)
+        },
+        Ok(x) => x,
+    };
 
-        s.spawn(|| {
-            while let Ok(u8s) = rxb.recv() {
-                if let Err(e) = writera.send(&u8s) {
-                    error!(target: "write error on channel 1", error = ?e);
-                }
-            }
-        });
-        s.spawn(|| {
-            loop {
-                let mut buf = Vec::with_capacity(model::TCP_BUFFER_SIZE);
-                match b.read(&mut buf) {
-                    Ok(n) => {
-                        buf.drain(n..);
-                        let _ = txb.send(buf.clone());
-                    },
-                    Err(e) => {
-                        debug!(?e);
-                        drop(txb);
-                        break;
-                    },
-                }
-            }
+    // generate salt and send it back to the relay
+    let (strong_encryption, salt) = crypt::new(&strong_key, &[])?;
+    c.send(&salt)?;
 
-            debug!("exiting");
-        });
-    });
+    debug!("sending password");
+    let bsend = crypt::encrypt(password.as_bytes(), &strong_encryption)?;
+    c.send(&bsend)?;
+    debug!("waiting for first ok");
+    let enc = c.receive()?;
+    let data = String::from_utf8(crypt::decrypt(&enc, &strong_encryption)?)?;
+    let (banner, ipaddr) = match data.split_once("|||") {
+        None => anyhow::bail!("{}", data),
+        Some(x) => x,
+    };
+
+    debug!("sending room");
+    let bsend = crypt::encrypt(room.as_bytes(), &strong_encryption)?;
+    c.send(&bsend)?;
+    debug!("waiting for room confirmation");
+    let enc = c.receive()?;
+    let data = crypt::decrypt(&enc, &strong_encryption)?;
+    if data != b"ok" {
+        anyhow::bail!("got bad response: {}", String::from_utf8_lossy(&data))
+    }
 
-    Ok(())
+    debug!("all set");
+    c.set_max_frame_size(comm::DEFAULT_MAX_FRAME_SIZE);
+    Ok((c, banner.into(), ipaddr.into()))
 }
 
-fn delete_old_rooms(
+// delete_old_rooms runs every interval until stop_rx fires or is dropped with the server
+async fn delete_old_rooms(
     rooms: roomMap,
-    mut stop_rx: crossbeam_channel::Receiver<()>,
-    room_cleanup_interval: Duration,
-    room_ttl: Duration,
+    options: Options,
+    mut stop_rx: oneshot::Receiver<()>,
 ) {
     loop {
-        std::thread::sleep(room_cleanup_interval);
-        match stop_rx.try_recv() {
-            Ok(_) => {
+        tokio::select! {
+            _ = &mut stop_rx => {
+                debug!("room cleanup stopped");
                 return;
             },
-            Err(e) => {
-                if e.is_disconnected() {
-                    return;
-                }
-            },
+            _ = tokio::time::sleep(options.room_cleanup_interval) => {},
         }
 
-        let mut rooms_to_delete = vec![];
-        {
-            let lock = rooms.read();
-            for (key, room) in lock.iter() {
-                if room.opened.elapsed() > room_ttl {
-                    rooms_to_delete.push(key.to_string());
-                }
-            }
-        }
-        {
-            let mut lock = rooms.write();
-            for room in rooms_to_delete {
-                lock.remove(&room);
-                debug!(target: "room cleaned up", room = room);
-            }
-        }
+        room::delete_old(&rooms, &options.metrics, options.room_ttl);
+        options.limiter.prune();
+        options.lockout.prune();
     }
 }
@@ -0,0 +1,142 @@
+use std::io;
+use std::net::Shutdown;
+
+use socket2::SockRef;
+use tokio::net::TcpStream;
+
+// Pipe copies between the two connections of a room until both sides are done.
+// When one side stops sending, the other is told with a half-close, so the data
+// still on its way back is not cut off. Forwarded bytes are given to tally as they go,
+// so they are known even when a side resets the connection, and an error from tally
+// ends the pipe.
+pub async fn pipe(
+    a: &TcpStream,
+    b: &TcpStream,
+    tally: &Tally<'_>,
+) -> io::Result<()> {
+    tokio::try_join!(one_way(a, b, tally), one_way(b, a, tally))?;
+    Ok(())
+}
+
+pub type Tally<'a> = dyn Fn(u64) -> io::Result<()> + Sync + 'a;
+
+// zero-copy, the data moves from socket to socket through a kernel pipe
+#[cfg(target_os = "linux")]
+async fn one_way(
+    src: &TcpStream,
+    dst: &TcpStream,
+    tally: &Tally<'_>,
+) -> io::Result<()> {
+    use std::os::fd::AsRawFd;
+    use tokio::io::Interest;
+
+    let (pipe_r, pipe_w) = splice::pipe()?;
+    loop {
+        // the kernel pipe is always empty here, so EAGAIN can only come from the socket
+        let n = loop {
+            src.readable().await?;
+            match src.try_io(Interest::READABLE, || {
+                splice::splice(src.as_raw_fd(), pipe_w.as_raw_fd(), crate::model::TCP_BUFFER_SIZE)
+            }) {
+                Ok(n) => break n,
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock => continue,
+                Err(e) => return Err(e),
+            }
+        };
+        if n == 0 {
+            break;
+        }
+        // drain the kernel pipe completely, writes may be partial
+        let mut pending = n;
+        while pending > 0 {
+            dst.writable().await?;
+            match dst.try_io(Interest::WRITABLE, || {
+                splice::splice(pipe_r.as_raw_fd(), dst.as_raw_fd(), pending)
+            }) {
+                Ok(m) => {
+                    pending -= m;
+                    tally(m as u64)?;
+                },
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock => continue,
+                Err(e) => return Err(e),
+            }
+        }
+    }
+    half_close(dst)
+}
+
+#[cfg(not(target_os = "linux"))]
+async fn one_way(
+    src: &TcpStream,
+    dst: &TcpStream,
+    tally: &Tally<'_>,
+) -> io::Result<()> {
+    let mut buf = vec![0u8; crate::model::TCP_BUFFER_SIZE];
+    loop {
+        src.readable().await?;
+        let n = match src.try_read(&mut buf) {
+            Ok(0) => break,
+            Ok(n) => n,
+            Err(e) if e.kind() == io::ErrorKind::WouldBlock => continue,
+            Err(e) => return Err(e),
+        };
+        let mut written = 0;
+        while written < n {
+            dst.writable().await?;
+            match dst.try_write(&buf[written..n]) {
+                Ok(m) => {
+                    written += m;
+                    tally(m as u64)?;
+                },
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock => continue,
+                Err(e) => return Err(e),
+            }
+        }
+    }
+    half_close(dst)
+}
+
+// the peer may already be gone, that is the end of the room anyway
+fn half_close(stream: &TcpStream) -> io::Result<()> {
+    match SockRef::from(stream).shutdown(Shutdown::Write) {
+        Err(e) if e.kind() == io::ErrorKind::NotConnected => Ok(()),
+        rst => rst,
+    }
+}
+
+#[cfg(target_os = "linux")]
+mod splice {
+    use std::io;
+    use std::os::fd::{FromRawFd, OwnedFd, RawFd};
+
+    // Pipe returns the read and write end of a non blocking kernel pipe
+    pub fn pipe() -> io::Result<(OwnedFd, OwnedFd)> {
+        let mut fds = [0 as libc::c_int; 2];
+        if unsafe { libc::pipe2(fds.as_mut_ptr(), libc::O_NONBLOCK | libc::O_CLOEXEC) } < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        // SAFETY: pipe2 just opened both descriptors and nothing else owns them
+        Ok(unsafe { (OwnedFd::from_raw_fd(fds[0]), OwnedFd::from_raw_fd(fds[1])) })
+    }
+
+    pub fn splice(
+        from: RawFd,
+        to: RawFd,
+        len: usize,
+    ) -> io::Result<usize> {
+        let n = unsafe {
+            libc::splice(
+                from,
+                std::ptr::null_mut(),
+                to,
+                std::ptr::null_mut(),
+                len,
+                libc::SPLICE_F_MOVE | libc::SPLICE_F_NONBLOCK,
+            )
+        };
+        if n < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(n as usize)
+    }
+}
@@ -0,0 +1,154 @@
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use parking_lot::RwLock;
+use tokio::sync::oneshot;
+use tracing::debug;
+
+use super::limits::Admission;
+use super::tenants::TenantSlot;
+use super::Metrics;
+use crate::comm;
+
+static NEXT_ROOM_ID: AtomicU64 = AtomicU64::new(1);
+
+// Member is a client in a room, with what it was admitted with
+pub struct Member {
+    pub comm: comm::AsyncComm,
+    // only held, it frees the share of the limits of the client when the room is done
+    pub _admission: Admission,
+    // the room is charged to this tenant
+    pub tenant: Option<TenantSlot>,
+}
+
+#[allow(non_camel_case_types)]
+pub type roomMap = Arc<RwLock<HashMap<String, roomInfo>>>;
+
+// RoomState is where a room is in its life: waiting, paired, piping, closed.
+// A room only moves forward, the lock is held for a transition and never for I/O.
+pub enum RoomState {
+    // the first client is in, the second hands its connection to the task of the first
+    Waiting(oneshot::Sender<Member>),
+    // the second client took the seat and is being told so
+    Paired,
+    Piping,
+    // done, the name can be used by a new room
+    Closed,
+}
+
+impl RoomState {
+    pub fn name(&self) -> &'static str {
+        match self {
+            RoomState::Waiting(_) => "waiting",
+            RoomState::Paired => "paired",
+            RoomState::Piping => "piping",
+            RoomState::Closed => "closed",
+        }
+    }
+}
+
+#[allow(non_camel_case_types)]
+pub struct roomInfo {
+    // tells this room apart from a later one with the same name
+    id: u64,
+    state: RoomState,
+    opened: Instant,
+}
+
+// Seat is what a room had for a client
+pub enum Seat {
+    First(u64, oneshot::Receiver<Member>),
+    Second(u64, oneshot::Sender<Member>),
+    Full,
+}
+
+pub fn new_map() -> roomMap {
+    Arc::new(RwLock::new(HashMap::new()))
+}
+
+// TakeSeat puts a client in the room room_key, a new room is opened
+// when there is none or the last one with that name is closed
+pub fn take_seat(
+    rooms: &roomMap,
+    metrics: &Metrics,
+    room_key: &str,
+) -> Seat {
+    let mut lock = rooms.write();
+    if let Some(room) = lock.get_mut(room_key) {
+        match std::mem::replace(&mut room.state, RoomState::Paired) {
+            RoomState::Waiting(tx) => {
+                debug!("room {} has 2", room_key);
+                metrics.rooms_paired.fetch_add(1, Ordering::Relaxed);
+                return Seat::Second(room.id, tx);
+            },
+            RoomState::Closed => {},
+            state => {
+                room.state = state;
+                return Seat::Full;
+            },
+        }
+    }
+
+    let (tx, rx) = oneshot::channel();
+    let id = NEXT_ROOM_ID.fetch_add(1, Ordering::Relaxed);
+    lock.insert(
+        room_key.to_string(),
+        roomInfo {
+            id,
+            state: RoomState::Waiting(tx),
+            opened: Instant::now(),
+        },
+    );
+    debug!("room {} has 1", room_key);
+    metrics.rooms_created.fetch_add(1, Ordering::Relaxed);
+    metrics.rooms_active.fetch_add(1, Ordering::Relaxed);
+    Seat::First(id, rx)
+}
+
+// SetState moves the room on, unless it was cleaned up or replaced meanwhile
+pub fn set_state(
+    rooms: &roomMap,
+    metrics: &Metrics,
+    room_key: &str,
+    id: u64,
+    state: RoomState,
+) {
+    let mut lock = rooms.write();
+    if let Some(room) = lock.get_mut(room_key).filter(|x| x.id == id) {
+        if matches!(room.state, RoomState::Closed) {
+            return;
+        }
+        debug!("room {} is {}", room_key, state.name());
+        if matches!(state, RoomState::Closed) {
+            metrics.rooms_active.fetch_sub(1, Ordering::Relaxed);
+        }
+        room.state = state;
+    }
+}
+
+// DeleteOld forgets closed rooms and those opened more than ttl ago,
+// a first client still waiting in one of them is told by its channel.
+// Piping rooms stay until they are done or idle.
+pub fn delete_old(
+    rooms: &roomMap,
+    metrics: &Metrics,
+    room_ttl: Duration,
+) {
+    let mut lock = rooms.write();
+    lock.retain(|key, room| {
+        match room.state {
+            RoomState::Closed => return false,
+            RoomState::Piping => return true,
+            _ => {},
+        }
+        if room.opened.elapsed() > room_ttl {
+            debug!(target: "room cleaned up", room = key, state = room.state.name());
+            metrics.rooms_expired.fetch_add(1, Ordering::Relaxed);
+            metrics.rooms_active.fetch_sub(1, Ordering::Relaxed);
+            return false;
+        }
+        true
+    });
+}
@@ -0,0 +1,394 @@
+use std::collections::HashMap;
+use std::io;
+use std::sync::{Arc, OnceLock};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use anyhow::Context;
+use parking_lot::{Mutex, MutexGuard};
+use sha2::{Digest, Sha256};
+use subtle::ConstantTimeEq;
+
+use super::lockout;
+use super::Metrics;
+use crate::utils;
+
+const DAY: u64 = 24 * 3600;
+
+// TenantLimit is the limit of a tenant that turned a room away or closed it
+#[derive(Debug, Clone, Copy)]
+pub enum TenantLimit {
+    Rooms,
+    Quota,
+    TransferSize,
+}
+
+impl TenantLimit {
+    pub fn reason(&self) -> &'static str {
+        match self {
+            TenantLimit::Rooms => "tenant_rooms",
+            TenantLimit::Quota => "tenant_quota",
+            TenantLimit::TransferSize => "tenant_transfer_size",
+        }
+    }
+
+    // Message is what the client is told
+    pub fn message(&self) -> &'static str {
+        match self {
+            TenantLimit::Rooms => "too many rooms",
+            TenantLimit::Quota => "daily quota exceeded",
+            TenantLimit::TransferSize => "transfer too large",
+        }
+    }
+}
+
+#[derive(Debug)]
+enum Secret {
+    Plain(String),
+    // lowercase hex of the sha256 of the password
+    Sha256(String),
+}
+
+#[derive(Debug, Default)]
+struct Usage {
+    // days since the epoch, in utc
+    day: u64,
+    bytes: u64,
+}
+
+// Tenant is a team sharing the relay under its own password
+#[derive(Debug)]
+pub struct Tenant {
+    pub name: String,
+    secret: Secret,
+    // zero is no limit for all of them
+    max_rooms: usize,
+    daily_quota: u64,
+    max_transfer: u64,
+    usage: Mutex<Usage>,
+    // bytes piped so far by every room of the tenant, and how many connections are in it.
+    // A transfer uses the same room on several ports, so it is counted once here.
+    rooms: Mutex<HashMap<String, (usize, u64)>>,
+}
+
+impl Tenant {
+    fn matches(
+        &self,
+        password: &[u8],
+    ) -> bool {
+        match &self.secret {
+            Secret::Plain(x) => lockout::password_matches(password, x.as_bytes()),
+            Secret::Sha256(x) => {
+                let given = format!("{:x}", Sha256::digest(password));
+                given.as_bytes().ct_eq(x.as_bytes()).into()
+            },
+        }
+    }
+
+    // Join takes a slot in room_key for a client, unless a limit of the tenant is reached
+    pub fn join(
+        self: &Arc<Self>,
+        room_key: &str,
+    ) -> Result<TenantSlot, TenantLimit> {
+        if self.daily_quota > 0 && self.usage().bytes >= self.daily_quota {
+            return Err(TenantLimit::Quota);
+        }
+        let mut lock = self.rooms.lock();
+        let open = lock.len();
+        match lock.get_mut(room_key) {
+            Some((_, piped)) if self.max_transfer > 0 && *piped >= self.max_transfer => {
+                return Err(TenantLimit::TransferSize);
+            },
+            Some((count, _)) => *count += 1,
+            None if self.max_rooms > 0 && open >= self.max_rooms => {
+                return Err(TenantLimit::Rooms);
+            },
+            None => {
+                lock.insert(room_key.to_string(), (1, 0));
+            },
+        }
+        Ok(TenantSlot {
+            tenant: self.clone(),
+            room_key: room_key.to_string(),
+        })
+    }
+
+    // usage is what was used today, a new day starts from zero
+    fn usage(&self) -> MutexGuard<'_, Usage> {
+        let mut usage = self.usage.lock();
+        let day = current_day();
+        if usage.day != day {
+            *usage = Usage { day, bytes: 0 };
+        }
+        usage
+    }
+
+    // charge adds bytes piped in room_key, it tells which limit is over afterwards
+    fn charge(
+        &self,
+        room_key: &str,
+        bytes: u64,
+    ) -> Option<TenantLimit> {
+        let piped = {
+            let mut lock = self.rooms.lock();
+            match lock.get_mut(room_key) {
+                None => bytes,
+                Some((_, piped)) => {
+                    *piped += bytes;
+                    *piped
+                },
+            }
+        };
+        let used = {
+            let mut usage = self.usage();
+            usage.bytes += bytes;
+            usage.bytes
+        };
+        if self.max_transfer > 0 && piped > self.max_transfer {
+            return Some(TenantLimit::TransferSize);
+        }
+        if self.daily_quota > 0 && used > self.daily_quota {
+            return Some(TenantLimit::Quota);
+        }
+        None
+    }
+}
+
+// TenantSlot is the place of a client in a room of its tenant, it is given back when dropped
+#[derive(Debug)]
+pub struct TenantSlot {
+    tenant: Arc<Tenant>,
+    room_key: String,
+}
+
+impl TenantSlot {
+    pub fn tenant(&self) -> &str {
+        &self.tenant.name
+    }
+}
+
+impl Drop for TenantSlot {
+    fn drop(&mut self) {
+        let mut lock = self.tenant.rooms.lock();
+        if let Some((count, _)) = lock.get_mut(&self.room_key) {
+            *count -= 1;
+            if *count == 0 {
+                lock.remove(&self.room_key);
+            }
+        }
+    }
+}
+
+// Tenants are the credentials of the relay, without any the relay password is used
+#[derive(Debug, Default)]
+pub struct Tenants {
+    tenants: Vec<Arc<Tenant>>,
+}
+
+impl Tenants {
+    // Load reads one tenant per line as key=value pairs:
+    //   tenant=<name> password=<password> or password-sha256=<hex>
+    //   max-rooms=<n> daily-quota=<size> max-transfer=<size>
+    // the limits are optional, lines starting with # are comments
+    pub fn load(path: &str) -> anyhow::Result<Tenants> {
+        let text = std::fs::read_to_string(path)
+            .with_context(|| format!("could not read credentials {}", path))?;
+        Tenants::parse(&text).with_context(|| format!("could not parse credentials {}", path))
+    }
+
+    fn parse(text: &str) -> anyhow::Result<Tenants> {
+        let mut tenants: Vec<Arc<Tenant>> = Vec::new();
+        for (i, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let (mut name, mut secret) = (None, None);
+            let (mut max_rooms, mut daily_quota, mut max_transfer) = (0, 0, 0);
+            for field in line.split_whitespace() {
+                let (key, value) = match field.split_once('=') {
+                    None => anyhow::bail!("line {}: expected key=value, got {:?}", i + 1, field),
+                    Some(x) => x,
+                };
+                let size =
+                    || utils::parse_byte_count(value).with_context(|| format!("line {}", i + 1));
+                match key {
+                    "tenant" => name = Some(value.to_string()),
+                    "password" => secret = Some(Secret::Plain(value.to_string())),
+                    "password-sha256" => {
+                        if value.len() != 64 || !value.chars().all(|c| c.is_ascii_hexdigit()) {
+                            anyhow::bail!("line {}: password-sha256 must be 64 hex digits", i + 1)
+                        }
+                        secret = Some(Secret::Sha256(value.to_ascii_lowercase()))
+                    },
+                    "max-rooms" => {
+                        max_rooms = value
+                            .parse()
+                            .with_context(|| format!("line {}: bad max-rooms", i + 1))?
+                    },
+                    "daily-quota" => daily_quota = size()?,
+                    "max-transfer" => max_transfer = size()?,
+                    _ => anyhow::bail!("line {}: unknown key {:?}", i + 1, key),
+                }
+            }
+            let (Some(name), Some(secret)) = (name, secret) else {
+                anyhow::bail!("line {}: a tenant needs a name and a password", i + 1)
+            };
+            if tenants.iter().any(|x| x.name == name) {
+                anyhow::bail!("line {}: tenant {} is listed twice", i + 1, name)
+            }
+            tenants.push(Arc::new(Tenant {
+                name,
+                secret,
+                max_rooms,
+                daily_quota,
+                max_transfer,
+                usage: Default::default(),
+                rooms: Default::default(),
+            }));
+        }
+        if tenants.is_empty() {
+            anyhow::bail!("no tenants")
+        }
+        Ok(Tenants { tenants })
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.tenants.is_empty()
+    }
+
+    // Find returns the tenant with this password, every tenant is tried so the time
+    // it takes does not tell which one matched
+    pub fn find(
+        &self,
+        password: &[u8],
+    ) -> Option<Arc<Tenant>> {
+        let mut found = None;
+        for tenant in &self.tenants {
+            if tenant.matches(password) && found.is_none() {
+                found = Some(tenant.clone());
+            }
+        }
+        found
+    }
+}
+
+// Meter charges the bytes of a piping room to the tenants in it, as they are piped
+pub struct Meter<'a> {
+    slots: Vec<&'a TenantSlot>,
+    metrics: &'a Metrics,
+    // the first tenant that went over a limit
+    over: OnceLock<(&'a str, TenantLimit)>,
+}
+
+impl<'a> Meter<'a> {
+    // both clients of a room can be of the same tenant, it is charged once then
+    pub fn new(
+        mut slots: Vec<&'a TenantSlot>,
+        metrics: &'a Metrics,
+    ) -> Meter<'a> {
+        slots.dedup_by(|a, b| Arc::ptr_eq(&a.tenant, &b.tenant));
+        Meter {
+            slots,
+            metrics,
+            over: OnceLock::new(),
+        }
+    }
+
+    // Charge adds bytes to every tenant of the room and its metrics, an error stops the pipe
+    pub fn charge(
+        &self,
+        bytes: u64,
+    ) -> io::Result<()> {
+        for slot in &self.slots {
+            self.metrics.tenant_piped(slot.tenant(), bytes);
+        }
+        for slot in self.slots.iter().copied() {
+            if let Some(limit) = slot.tenant.charge(&slot.room_key, bytes) {
+                let _ = self.over.set((slot.tenant(), limit));
+                return Err(io::Error::new(io::ErrorKind::Other, limit.message()));
+            }
+        }
+        Ok(())
+    }
+
+    pub fn over(&self) -> Option<(&'a str, TenantLimit)> {
+        self.over.get().copied()
+    }
+}
+
+fn current_day() -> u64 {
+    let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default();
+    now.as_secs() / DAY
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // sha256 of "hunter2"
+    const HUNTER2: &str = "f52fbd32b2b3b86ff88ef6c490628285f482af15ddcb29541f94bcf526a3f6c7";
+
+    #[test]
+    fn parse_tenants_and_limits() {
+        let tenants = Tenants::parse(&format!(
+            "# name, password and limits\n\
+             tenant=red password=pw1 max-rooms=3 daily-quota=5M max-transfer=1.5GB\n\
+             \n\
+             tenant=blue password-sha256={}\n",
+            HUNTER2.to_ascii_uppercase()
+        ))
+        .unwrap();
+        let red = tenants.find(b"pw1").unwrap();
+        assert_eq!(red.name, "red");
+        assert_eq!(red.max_rooms, 3);
+        assert_eq!(red.daily_quota, 5 * 1024 * 1024);
+        assert_eq!(red.max_transfer, 1536 * 1024 * 1024);
+        let blue = tenants.find(b"hunter2").unwrap();
+        assert_eq!((blue.max_rooms, blue.daily_quota, blue.max_transfer), (0, 0, 0));
+        assert!(tenants.find(b"pw2").is_none());
+        assert!(tenants.find(HUNTER2.as_bytes()).is_none());
+    }
+
+    #[test]
+    fn parse_errors_name_the_line() {
+        for (text, message) in [
+            (
+                "tenant=red password-sha256=abc",
+                "line 1: password-sha256 must be 64 hex digits",
+            ),
+            (
+                &format!("tenant=red password-sha256={}", HUNTER2.replace('f', "g")),
+                "line 1: password-sha256",
+            ),
+            ("tenant=red password=pw1 daily-quota=5X", "line 1"),
+            ("tenant=red password=pw1 max-rooms=-1", "line 1: bad max-rooms"),
+            ("tenant=red password=pw1 colour=red", "line 1: unknown key"),
+            ("tenant=red\n", "line 1: a tenant needs a name and a password"),
+            (
+                "tenant=red password=a\ntenant=red password=b",
+                "line 2: tenant red is listed twice",
+            ),
+            ("password", "line 1: expected key=value"),
+            ("# nobody yet\n", "no tenants"),
+        ] {
+            let err = Tenants::parse(text).unwrap_err();
+            assert!(err.to_string().starts_with(message), "{:?}: {}", text, err);
+        }
+    }
+
+    #[test]
+    fn join_and_charge_keep_the_limits() {
+        let tenants =
+            Tenants::parse("tenant=red password=pw1 max-rooms=1 max-transfer=1k").unwrap();
+        let red = tenants.find(b"pw1").unwrap();
+        let first = red.join("room1").unwrap();
+        // the other client of the room is no new room
+        let second = red.join("room1").unwrap();
+        assert!(matches!(red.join("room2"), Err(TenantLimit::Rooms)));
+        assert!(red.charge("room1", 1024).is_none());
+        assert!(matches!(red.charge("room1", 1), Some(TenantLimit::TransferSize)));
+        drop((first, second));
+        // the room is given back with its last client
+        assert!(red.join("room2").is_ok());
+    }
+}
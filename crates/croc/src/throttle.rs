@@ -0,0 +1,56 @@
+use std::time::{Duration, Instant};
+
+use parking_lot::Mutex;
+
+use crate::model;
+
+struct Bucket {
+    tokens: f64,
+    last: Instant,
+}
+
+// Throttle is a token bucket for the bytes of a transfer, shared by all of its connections.
+// Every connection takes what it sends and sleeps off the debt, so together they stay
+// at the rate, while a short burst up to the size of the bucket goes out right away.
+pub struct Throttle {
+    // bytes per second
+    rate: f64,
+    burst: f64,
+    bucket: Mutex<Bucket>,
+}
+
+impl Throttle {
+    pub fn new(rate: u64) -> Throttle {
+        // a quarter of a second of data, but at least a whole chunk
+        let burst = (rate as f64 / 4.0).max(model::TCP_BUFFER_SIZE as f64);
+        Throttle {
+            rate: rate as f64,
+            burst,
+            bucket: Mutex::new(Bucket {
+                tokens: burst,
+                last: Instant::now(),
+            }),
+        }
+    }
+
+    // Take blocks until n more bytes may be sent
+    pub fn take(
+        &self,
+        n: u64,
+    ) {
+        let wait = {
+            let mut bucket = self.bucket.lock();
+            let now = Instant::now();
+            let refill = now.duration_since(bucket.last).as_secs_f64() * self.rate;
+            bucket.tokens = (bucket.tokens + refill).min(self.burst);
+            bucket.last = now;
+            // the debt is left in the bucket, connections after this one wait for it too
+            bucket.tokens -= n as f64;
+            if bucket.tokens >= 0.0 {
+                return;
+            }
+            Duration::from_secs_f64(-bucket.tokens / self.rate)
+        };
+        std::thread::sleep(wait);
+    }
+}
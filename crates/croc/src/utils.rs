@@ -1,9 +1,13 @@
+use std::io::Write;
 use std::net::{SocketAddr, TcpStream};
 use std::os::unix::fs::PermissionsExt;
 use std::time::Duration;
 use std::{fs, path::PathBuf};
 
 use anyhow::Context;
+use rand::{rngs::StdRng, Rng, SeedableRng};
+
+use crate::mnemonic;
 
 pub fn find_open_ports(
     host: &str,
@@ -33,6 +37,72 @@ pub fn find_open_ports(
     open_ports
 }
 
+// add the default port to an address that does not have one
+pub fn address_with_port(
+    address: &str,
+    port: &str,
+) -> String {
+    if address.parse::<SocketAddr>().is_ok() {
+        return address.into();
+    }
+    match address.matches(':').count() {
+        // plain ipv6 address, e.g. ::1
+        n if n >= 2 && !address.starts_with('[') => format!("[{}]:{}", address, port),
+        0 => format!("{}:{}", address, port),
+        _ if address.ends_with(']') => format!("{}:{}", address, port),
+        _ => address.into(),
+    }
+}
+
+// GetRandomName returns a code phrase: a 4 digit pin followed by words
+// encoding 4 random bytes, e.g. 4821-maple-orbit-tiger-cocoa
+pub fn get_random_name() -> String {
+    let mut rng = StdRng::from_entropy();
+    let pin: u16 = rng.gen_range(0..10000);
+    let bs: [u8; 4] = rng.gen();
+    let mut result = vec![format!("{:04}", pin)];
+    result.extend(mnemonic::encode(&bs).into_iter().map(String::from));
+    result.join("-")
+}
+
+// ByteCountDecimal converts bytes to a human readable byte string
+pub fn byte_count_decimal(b: u64) -> String {
+    const UNIT: u64 = 1024;
+    if b < UNIT {
+        return format!("{} B", b);
+    }
+    let (mut div, mut exp) = (UNIT, 0);
+    let mut n = b / UNIT;
+    while n >= UNIT {
+        div *= UNIT;
+        exp += 1;
+        n /= UNIT;
+    }
+    format!("{:.1} {}B", b as f64 / div as f64, "kMGTPE".as_bytes()[exp] as char)
+}
+
+// ParseByteCount reads sizes like 512, 64k, 5M or 1.5GB, in the units of byte_count_decimal
+pub fn parse_byte_count(s: &str) -> anyhow::Result<u64> {
+    let s = s.trim();
+    let digits = s.find(|c: char| !c.is_ascii_digit() && c != '.').unwrap_or(s.len());
+    let (number, unit) = s.split_at(digits);
+    let number: f64 = match number.parse() {
+        Err(_) => anyhow::bail!("bad size {:?}", s),
+        Ok(x) => x,
+    };
+    let unit = unit.trim().trim_end_matches(['B', 'b']);
+    let exp = match unit.to_ascii_lowercase().as_str() {
+        "" => 0,
+        "k" => 1,
+        "m" => 2,
+        "g" => 3,
+        "t" => 4,
+        "p" => 5,
+        _ => anyhow::bail!("bad size {:?}", s),
+    };
+    Ok((number * 1024f64.powi(exp)) as u64)
+}
+
 // Get or create home directory
 pub fn get_config_dir(require: bool) -> anyhow::Result<String> {
     let mut homedir = PathBuf::new();
@@ -69,14 +139,10 @@ pub fn get_config_dir(require: bool) -> anyhow::Result<String> {
 
 // GetInput returns the input with a given prompt
 pub fn get_input(prompt: &[u8]) -> anyhow::Result<String> {
-    // let mut stderr = tokio::io::stderr();
-    // stderr.write_all(prompt)?;
-    // stderr.flush()?;
-    // let mut bytes = vec![];
-    // let mut buffer = BufReader::new(tokio::io::stdin());
-    // let _ = buffer.read_until(b'\n', &mut bytes)?;
-    // let input = std::str::from_utf8(&bytes)?;
-    // Ok(input.trim().to_string())
-    // @fri3nd TODO
-    todo!()
+    let mut stderr = std::io::stderr();
+    stderr.write_all(prompt)?;
+    stderr.flush()?;
+    let mut input = String::new();
+    std::io::stdin().read_line(&mut input)?;
+    Ok(input.trim().to_string())
 }
@@ -1,13 +1,35 @@
+use std::collections::HashMap;
+use std::net::IpAddr;
+
 use pnet::datalink;
 
-use crate::{PeerDiscovery, Settings};
+use crate::{IPVersion, PeerDiscovery, Settings};
+
+// https://en.wikipedia.org/wiki/User_Datagram_Protocol#Packet_structure
+pub(crate) const MAX_DATAGRAM_SIZE: usize = 66507;
 
 // initialize returns a new peerDiscovery object which can be used to discover peers.
 // The settings are optional. If any setting is not supplied, then defaults are used.
 // See the Settings for more information.
-pub(crate) fn initialize(_settings: &Settings) -> anyhow::Result<PeerDiscovery> {
-    // @fri3nd TODO
-    todo!()
+pub(crate) fn initialize(settings: &Settings) -> anyhow::Result<PeerDiscovery> {
+    let mut s = settings.clone();
+    if s.multicast_address.is_empty() {
+        s.multicast_address = match s.ip_version {
+            IPVersion::V4 => "239.255.255.250".into(),
+            IPVersion::V6 => "ff02::c".into(),
+        };
+    }
+    if s.payload.is_empty() {
+        s.payload = b"hi".to_vec();
+    }
+    if s.delay.is_zero() {
+        anyhow::bail!("delay between broadcasts must be positive")
+    }
+
+    Ok(PeerDiscovery {
+        settings: s,
+        received: HashMap::new(),
+    })
 }
 
 // filterInterfaces returns a list of valid network interfaces
@@ -16,8 +38,16 @@ pub(crate) fn filter_interfaces(use_ipv4: bool) -> Vec<datalink::NetworkInterfac
     interfaces
         .into_iter()
         // Interface must be up and either support multicast or be a loopback interface.
-        .filter(|x| x.is_up() || x.is_loopback() || x.is_multicast())
+        .filter(|x| x.is_up() && (x.is_loopback() || x.is_multicast()))
         .filter(|x| x.ips.iter().any(|y| y.is_ipv4() == use_ipv4))
         // .collect::<Vec<datalink::NetworkInterface>>()
         .collect()
 }
+
+// localAddresses returns the addresses of all interfaces of this machine
+pub(crate) fn local_addresses() -> Vec<IpAddr> {
+    datalink::interfaces()
+        .iter()
+        .flat_map(|x| x.ips.iter().map(|y| y.ip()))
+        .collect()
+}
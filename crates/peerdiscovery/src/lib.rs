@@ -1,3 +1,4 @@
+use std::collections::HashMap;
 use std::time::Duration;
 
 mod internal;
@@ -16,9 +17,8 @@ pub enum IPVersion {
 // PeerDiscovery is the object that can do the discovery for finding LAN peers.
 pub struct PeerDiscovery {
     pub settings: Settings,
-    // received map[string]*PeerState
-    // sync.RWMutex
-    // exit bool
+    // received holds the payloads of the discovered peers, by address
+    received: HashMap<String, Vec<u8>>,
 }
 
 // Discovered is the structure of the discovered peers,
@@ -36,6 +36,8 @@ pub struct Discovered {
 // doing peer discovery.
 #[derive(Clone)]
 pub struct Settings {
+    // Limit is the number of peers to discover, use < 1 for unlimited.
+    pub limit: i32,
     // Port is the port to broadcast on (the peers must also broadcast using the same port).
     // The default port is 9999.
     pub port: u16,
@@ -49,12 +51,14 @@ pub struct Settings {
     // Delay is the amount of time between broadcasts. The default delay is 1 second.
     pub delay: Duration,
     // TimeLimit is the amount of time to spend discovering, if the limit is not reached.
-    // A negative limit indiciates scanning until the limit was reached or, if an
+    // None indiciates scanning until the limit was reached or, if an
     // unlimited scanning was requested, no timeout.
     // The default time limit is 10 seconds.
-    pub time_limit: i64,
+    pub time_limit: Option<Duration>,
     // DisableBroadcast will not allow sending out a broadcast
     pub disable_broadcast: bool,
+    // AllowSelf will allow discovery the local machine (default false)
+    pub allow_self: bool,
     // IPVersion specifies the version of the Internet Protocol (default IPv4)
     pub ip_version: IPVersion,
 }
@@ -62,12 +66,14 @@ pub struct Settings {
 impl Default for Settings {
     fn default() -> Self {
         Self {
+            limit: -1,
             multicast_address: "".into(),
             port: 9999,
             payload: vec![],
             delay: Duration::from_secs(1),
-            time_limit: 10,
+            time_limit: Some(Duration::from_secs(10)),
             disable_broadcast: false,
+            allow_self: false,
             ip_version: IPVersion::V4,
         }
     }
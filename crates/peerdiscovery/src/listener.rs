@@ -1,8 +1,46 @@
-// PeerDiscovery is the object that can do the discovery for finding LAN peers.
-pub struct PeerDiscovery {
-    // settings Settings
+use std::net::{IpAddr, UdpSocket};
+use std::time::Instant;
 
-    // received map[string]*PeerState
-    // sync.RWMutex
-    // exit bool
+use crate::internal::MAX_DATAGRAM_SIZE;
+use crate::PeerDiscovery;
+
+impl PeerDiscovery {
+    // listen reads the packets sent to the multicast group until the deadline
+    // and remembers who sent them
+    pub(crate) fn listen(
+        &mut self,
+        socket: &UdpSocket,
+        until: Instant,
+        local_addresses: &[IpAddr],
+    ) -> anyhow::Result<()> {
+        let mut buf = vec![0u8; MAX_DATAGRAM_SIZE];
+        loop {
+            let now = Instant::now();
+            if now >= until {
+                return Ok(());
+            }
+            socket.set_read_timeout(Some(until - now))?;
+            let (n, src) = match socket.recv_from(&mut buf) {
+                Err(e)
+                    if matches!(
+                        e.kind(),
+                        std::io::ErrorKind::WouldBlock | std::io::ErrorKind::TimedOut
+                    ) =>
+                {
+                    return Ok(());
+                },
+                Err(e) => return Err(e.into()),
+                Ok(x) => x,
+            };
+
+            if !self.settings.allow_self && local_addresses.contains(&src.ip()) {
+                continue;
+            }
+            self.received.insert(src.ip().to_string(), buf[..n].to_vec());
+
+            if self.settings.limit > 0 && self.received.len() >= self.settings.limit as usize {
+                return Ok(());
+            }
+        }
+    }
 }
@@ -1,12 +1,7 @@
-use std::net::{
-    Ipv4Addr,
-    Ipv6Addr,
-    // IpAddr, ,
-    SocketAddr,
-    UdpSocket,
-};
-use std::str::FromStr;
-use std::time::{Duration, Instant};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, UdpSocket};
+use std::time::Instant;
+
+use socket2::{Domain, Protocol, SockRef, Socket, Type};
 
 use crate::{internal, Discovered, IPVersion, PeerDiscovery, Settings};
 
@@ -24,59 +19,100 @@ fn new_peer_discovery(settings: &[Settings]) -> anyhow::Result<(PeerDiscovery, V
     } else {
         settings.first().cloned().unwrap()
     };
-    let p = internal::initialize(&s)?;
-    // p.RLock()
-    let address: String =
-        SocketAddr::new(p.settings.multicast_address.parse()?, p.settings.port).to_string();
-    // p.RUnlock()
+    let mut p = internal::initialize(&s)?;
+    let group: IpAddr = p.settings.multicast_address.parse()?;
+    let address = SocketAddr::new(group, p.settings.port);
 
     let ifaces = internal::filter_interfaces(p.settings.ip_version == IPVersion::V4);
     if ifaces.is_empty() {
         anyhow::bail!("no multicast interface found")
     }
-    let socket = socket2::Socket::from(UdpSocket::bind(&address)?);
-    if p.settings.ip_version == IPVersion::V4 {
-        let group = Ipv4Addr::from_str(&p.settings.multicast_address)?;
-        for iface in &ifaces {
-            for ip in &iface.ips {
-                if let ipnetwork::IpNetwork::V4(x) = ip {
-                    socket.join_multicast_v4(group, x.network())?;
+    let socket = bind_multicast(&p.settings)?;
+    let sock_ref = SockRef::from(&socket);
+    let mut iface_addresses_v4: Vec<Ipv4Addr> = vec![];
+    match group {
+        IpAddr::V4(group) => {
+            for iface in &ifaces {
+                for ip in &iface.ips {
+                    if let ipnetwork::IpNetwork::V4(x) = ip {
+                        socket.join_multicast_v4(&group, &x.ip())?;
+                        iface_addresses_v4.push(x.ip());
+                    }
                 }
             }
-        }
-    } else {
-        let group = Ipv6Addr::from_str(&p.settings.multicast_address)?;
-        for iface in &ifaces {
-            socket.join_multicast_v6(&group, iface.index)?;
-        }
+            socket.set_multicast_loop_v4(true)?;
+            socket.set_multicast_ttl_v4(2)?;
+        },
+        IpAddr::V6(group) => {
+            for iface in &ifaces {
+                socket.join_multicast_v6(&group, iface.index)?;
+            }
+            socket.set_multicast_loop_v6(true)?;
+        },
     }
 
-    // @fri3nd TODO
-    // go p.listen(c)
-    // @fri3nd TODO
+    let local_addresses = internal::local_addresses();
     let start = Instant::now();
-    let mut interval = tokio::time::interval(p.settings.delay);
     loop {
-        if !s.disable_broadcast {
-            // let payload = p.settings.payload;
-            // write to multicast
-            // for _iface in &ifaces {
-            if socket.set_multicast_loop_v4(true).is_ok() {
-                let _ = socket.set_ttl(2);
-                let _ = socket.send_to(&p.settings.payload, &address);
+        if !p.settings.disable_broadcast {
+            // write to multicast on every interface
+            match group {
+                IpAddr::V4(_) => {
+                    for ip in &iface_addresses_v4 {
+                        if sock_ref.set_multicast_if_v4(ip).is_ok() {
+                            let _ = socket.send_to(&p.settings.payload, address);
+                        }
+                    }
+                },
+                IpAddr::V6(_) => {
+                    for iface in &ifaces {
+                        if sock_ref.set_multicast_if_v6(iface.index).is_ok() {
+                            let _ = socket.send_to(&p.settings.payload, address);
+                        }
+                    }
+                },
             }
-            // }
         }
 
-        // let _ = interval.tick();
-        if p.settings.time_limit > 0
-            && start.elapsed() > Duration::from_secs(p.settings.time_limit as u64)
-        {
+        // listen for the others until it is time to broadcast again
+        let until = Instant::now() + p.settings.delay;
+        p.listen(&socket, until, &local_addresses)?;
+
+        if p.settings.limit > 0 && p.received.len() >= p.settings.limit as usize {
+            break;
+        }
+        if p.settings.time_limit.is_some_and(|x| start.elapsed() > x) {
             break;
         }
     }
 
-    // Open up a connection
-    // @fri3nd TODO
-    todo!()
+    let discoveries = p
+        .received
+        .iter()
+        .map(|(address, payload)| Discovered {
+            address: address.clone(),
+            payload: payload.clone(),
+        })
+        .collect();
+    Ok((p, discoveries))
+}
+
+// every peer listens on the same port, so it has to be shared
+fn bind_multicast(settings: &Settings) -> anyhow::Result<UdpSocket> {
+    let (domain, addr) = match settings.ip_version {
+        IPVersion::V4 => {
+            (Domain::IPV4, SocketAddr::new(Ipv4Addr::UNSPECIFIED.into(), settings.port))
+        },
+        IPVersion::V6 => {
+            (Domain::IPV6, SocketAddr::new(Ipv6Addr::UNSPECIFIED.into(), settings.port))
+        },
+    };
+    let socket = Socket::new(domain, Type::DGRAM, Some(Protocol::UDP))?;
+    if settings.ip_version == IPVersion::V6 {
+        socket.set_only_v6(true)?;
+    }
+    socket.set_reuse_address(true)?;
+    socket.set_reuse_port(true)?;
+    socket.bind(&addr.into())?;
+    Ok(socket.into())
 }